@@ -4,18 +4,23 @@ wish.iter = require('wish.iter')
 wish.table = require('wish.table')
 wish.style = require('wish.style')
 wish.utf8 = require('wish.utf8')
+wish.snippet = require('wish.snippet')
+wish.prompt = require('wish.prompt')
+wish.plugins = require('wish.plugins')
 require('wish.keybind')
-require('wish.syntax-highlight').enable()
-require('wish.paste').enable()
-require('wish.completion').enable{
-    keybinds = {
-        ['<tab>'] = 'start',
-    }
-}
-require('wish.history-selector').enable{
-    keybinds = {
-        ['<c-r>'] = 'start',
-    }
+wish.plugins.setup{
+    {'wish.syntax-highlight'},
+    {'wish.paste'},
+    {'wish.completion', opts = {
+        keybinds = {
+            ['<tab>'] = 'start',
+        },
+    }},
+    {'wish.history-selector', opts = {
+        keybinds = {
+            ['<c-r>'] = 'start',
+        },
+    }},
 }
 
 local config_home = os.getenv('XDG_CONFIG_HOME')
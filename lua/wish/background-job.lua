@@ -5,7 +5,13 @@ local active_job = nil
 
 local NAMESPACE = wish.add_buf_highlight_namespace()
 
-M.PROMPT_TIMEOUT = 0.04 -- same as rlwrap
+wish.opt.define{
+    name = 'background_job.prompt_timeout',
+    type = 'number',
+    default = 0.04, -- same as rlwrap
+    doc = 'seconds to wait for more output before treating a line ending in : or ? as a prompt',
+    validate = function(value) return value >= 0, 'must not be negative' end,
+}
 
 M.BORDER_RUNNING = 'blue'
 M.BORDER_WAITING = M.BORDER_RUNNING
@@ -125,7 +131,7 @@ function M.run_in_background(command)
                     -- its a prompt, wait a bit to see if there is any more output
                     local marker = job.output_marker
                     wish.schedule(function()
-                        wish.sleep(M.PROMPT_TIMEOUT)
+                        wish.sleep(wish.opt.get('background_job.prompt_timeout'))
                         -- marker has not changed, so output/prompt has not changed
                         if marker == job.output_marker and jobs[msg] then
                             job.waiting_for_input = true
@@ -0,0 +1,42 @@
+return wish.plugin(function(wish, opts, plugin)
+
+    local NAMESPACE = wish.add_buf_highlight_namespace()
+
+    local styles = opts.styles or {
+        error = {underline = {style = 'curly', color = 'red'}},
+        warning = {underline = {style = 'curly', color = 'yellow'}},
+    }
+    local message_style = opts.message_style or {
+        error = {fg = 'red'},
+        warning = {fg = 'yellow'},
+    }
+
+    local msg_id = wish.set_message{hidden = true}
+
+    wish.add_event_callback('parse_change', function()
+        wish.clear_buf_highlights(NAMESPACE)
+
+        local _, _, _, diagnostics = wish.get_parse()
+        local messages = {}
+        for i = 1, #diagnostics do
+            local diag = diagnostics[i]
+            local style = styles[diag.severity]
+            if style then
+                wish.add_buf_highlight(wish.table.merge(wish.table.copy(style), {
+                    start = diag.start,
+                    finish = diag.finish,
+                    namespace = NAMESPACE,
+                }))
+            end
+            table.insert(messages, diag.message)
+        end
+
+        local severity = diagnostics[1] and diagnostics[1].severity
+        wish.set_message(wish.table.merge({
+            id = msg_id,
+            hidden = #messages == 0,
+            contents = table.concat(messages, '\n'),
+        }, message_style[severity] or {}))
+    end)
+
+end)
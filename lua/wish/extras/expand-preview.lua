@@ -0,0 +1,66 @@
+return wish.plugin(function(wish, opts, plugin)
+
+    local expand_opts = opts.expand or {}
+    local max_words = opts.max_words or 100
+    local delay = opts.delay or 0.1
+    -- ** can walk a huge directory tree
+    local recursive_glob = opts.recursive_glob or false
+    local msg = wish.set_message(wish.table.deep_merge({
+        hidden = true,
+        dim = true,
+    }, opts.style or {}))
+
+    local function find_word(cursor)
+        for _, cmd in ipairs(wish.parse_commands()) do
+            for _, word in ipairs(cmd.argv) do
+                if word.start <= cursor and cursor <= word.finish + 1 then
+                    return word
+                end
+            end
+        end
+    end
+
+    local function update()
+        local word = find_word(wish.get_cursor())
+        -- nothing to show if it expands to itself
+        local ok, words = false, nil
+        if word and (recursive_glob or expand_opts.glob == false or not word.text:find('**', 1, true)) then
+            ok, words = pcall(wish.expand, word.text, expand_opts)
+        end
+        if not ok or #words == 0 or (#words == 1 and words[1] == word.value) then
+            wish.set_message{id = msg, hidden = true}
+            return
+        end
+
+        local text = {}
+        for i = 1, math.min(#words, max_words) do
+            table.insert(text, wish.shell_quote(words[i]))
+        end
+        if #words > max_words then
+            table.insert(text, '... (' .. (#words - max_words) .. ' more)')
+        end
+        wish.set_message{id = msg, hidden = false, contents = table.concat(text, ' ')}
+    end
+
+    -- only expand once typing has paused
+    local next_update = nil
+    local function schedule_update()
+        local running = next_update
+        next_update = wish.time() + delay
+        if not running then
+            wish.schedule(function()
+                local wait = delay
+                while wait > 0 do
+                    wish.sleep(wait)
+                    wait = next_update - wish.time()
+                end
+                next_update = nil
+                update()
+            end)
+        end
+    end
+
+    wish.add_event_callback('buffer_change', schedule_update)
+    wish.add_event_callback('buffer_cursor_move', schedule_update)
+
+end)
@@ -17,7 +17,7 @@ return wish.plugin(function(wish, opts, plugin)
         ['('] = ')',
         ['{'] = '}',
     }
-    local RULES = {
+    local RULES = QUERY.compile{
         { {regex = '^[(){}]$', highlight = true} },
         { {kind = 'heredoc_end', highlight = true} },
         {
@@ -34,7 +34,7 @@ return wish.plugin(function(wish, opts, plugin)
         wish.clear_buf_highlights(NAMESPACE)
 
         local brackets = {}
-        QUERY.apply_rules(RULES, tokens, str, function(matches)
+        QUERY.apply_rules(RULES, function(matches)
             for i = 1, #matches do
                 if matches[i][1].highlight then
                     table.insert(brackets, matches[i][2])
@@ -16,8 +16,10 @@ return wish.plugin(function(wish, opts, plugin)
             clear_paste = id
             local _buffer, cursor = wish.get_buffer()
 
-            -- paste
-            wish.insert_at_cursor(data)
+            -- paste, along with anything buffer_change callbacks do, is one undo step
+            wish.undo_join(function()
+                wish.insert_at_cursor(data)
+            end)
 
             if not next(flash_style) then
                 -- no styling
@@ -3,9 +3,62 @@ wish.add_event_callback('init', function()
     init = true
 end)
 
-return function(plugin_fn)
+-- every plugin that is currently enabled
+local ENABLED = {}
+
+wish.opt.define{
+    name = 'plugin.max_errors',
+    type = 'integer',
+    default = 10,
+    doc = 'disable a plugin once its callbacks have failed this many times, 0 for no limit',
+    validate = function(value) return value >= 0, 'must not be negative' end,
+}
+wish.opt.define{
+    name = 'plugin.time_limit',
+    type = 'number',
+    default = 1,
+    doc = 'stop a plugin callback and count it as failed once it runs for longer than this many seconds without yielding, 0 for no limit',
+    validate = function(value) return value >= 0, 'must not be negative' end,
+}
+
+-- cached as callbacks may run while drawing, when options cannot be read
+local MAX_ERRORS = wish.opt.get('plugin.max_errors')
+local TIME_LIMIT = wish.opt.get('plugin.time_limit')
+wish.add_event_callback('option_changed', function(name)
+    if name == 'plugin.max_errors' then
+        MAX_ERRORS = wish.opt.get(name)
+    elseif name == 'plugin.time_limit' then
+        TIME_LIMIT = wish.opt.get(name)
+    end
+end)
+
+local function pack(...)
+    return {n = select('#', ...), ...}
+end
+
+local M = {}
+
+-- e.g. before reloading the config
+function M.disable_all()
+    for plugin_obj in pairs(ENABLED) do
+        plugin_obj.disable()
+    end
+end
+
+function M.new(plugin_fn, name)
+    if not name then
+        -- e.g. @/path/to/wish/completion.lua -> completion
+        local source = debug.getinfo(plugin_fn, 'S').source
+        name = source:match('([^/]+)%.lua$') or source
+    end
+
     local state = {
+        name = name,
         enabled = false,
+        -- how many times callbacks have failed since being enabled
+        errors = 0,
+        -- why it was disabled automatically
+        failure = nil,
         event_callbacks = {},
         render_callbacks = {},
         keymap_layers = {},
@@ -13,6 +66,9 @@ return function(plugin_fn)
         messages = {},
         highlight_namespaces = {},
         processes = {},
+        tasks = {},
+        watches = {},
+        listeners = {},
         vars = {},
     }
 
@@ -22,11 +78,70 @@ return function(plugin_fn)
         return state.enabled
     end
 
+    function plugin_obj.get_name()
+        return state.name
+    end
+
+    function plugin_obj.set_name(value)
+        state.name = value
+    end
+
+    -- returns the number of failed callbacks and why it was automatically disabled (if it was)
+    function plugin_obj.get_errors()
+        return state.errors, state.failure
+    end
+
+    local function auto_disable(reason)
+        if state.failure then
+            return
+        end
+        state.failure = reason
+        -- this may be in the middle of drawing, so disable it later
+        wish.schedule(function()
+            plugin_obj.disable()
+            local msg = 'plugin ' .. state.name .. ' was disabled: ' .. reason
+            wish.log.error(msg)
+            wish.show_error_message(msg)
+        end)
+    end
+
+    -- runs a callback on behalf of the plugin
+    -- so that failures and slow callbacks count against it
+    local function guard(callback)
+        if type(callback) ~= 'function' then
+            return callback
+        end
+
+        return function(...)
+            if not state.enabled or state.failure then
+                return
+            end
+
+            local time_limit = TIME_LIMIT
+            local result = pack(wish.call_with_time_limit(time_limit, callback, ...))
+            if result[1] then
+                return unpack(result, 2, result.n)
+            end
+
+            local err, timed_out = result[2], result[3]
+            if timed_out then
+                err = 'callback ran for longer than ' .. time_limit .. 's'
+            end
+            -- a slow callback counts as one failure, e.g. completion can be slow in a large directory
+            state.errors = state.errors + 1
+            if MAX_ERRORS > 0 and state.errors >= MAX_ERRORS then
+                auto_disable('callbacks failed ' .. state.errors .. ' times, last error: ' .. tostring(err))
+            end
+            error(err, 0)
+        end
+    end
+
     function plugin_obj.disable()
         if not state.enabled then
             return
         end
         state.enabled = false
+        ENABLED[plugin_obj] = nil
 
         -- kill all processes
         for i = #state.processes, 1, -1 do
@@ -37,9 +152,28 @@ return function(plugin_fn)
             state.processes[i] = nil
         end
 
-        -- clear all buffer highlights
+        -- cancel all tasks
+        for i = #state.tasks, 1, -1 do
+            state.tasks[i]:cancel()
+            state.tasks[i] = nil
+        end
+
+        -- stop all filesystem watches
+        for i = #state.watches, 1, -1 do
+            state.watches[i]:cancel()
+            state.watches[i] = nil
+        end
+
+        -- close all socket listeners
+        for i = #state.listeners, 1, -1 do
+            state.listeners[i]:close()
+            state.listeners[i] = nil
+        end
+
+        -- clear all buffer highlights and marks
         for i = #state.highlight_namespaces, 1, -1 do
             wish.clear_buf_highlights(state.highlight_namespaces[i])
+            wish.clear_marks(state.highlight_namespaces[i])
             state.highlight_namespaces[i] = nil
         end
 
@@ -80,6 +214,9 @@ return function(plugin_fn)
             return
         end
         state.enabled = true
+        state.errors = 0
+        state.failure = nil
+        ENABLED[plugin_obj] = true
 
         local function track_process(handle)
             if handle then
@@ -88,8 +225,16 @@ return function(plugin_fn)
             return handle
         end
 
+        local function track_task(task)
+            table.insert(state.tasks, task)
+            return task
+        end
+
         -- Create sub-proxies
         local async_proxy = setmetatable({
+            spawn_task = function(func, ...)
+                return track_task(wish.async.spawn_task(guard(func), ...))
+            end,
             spawn = function(...)
                 return track_process(wish.async.spawn(...))
             end,
@@ -101,10 +246,28 @@ return function(plugin_fn)
             end,
         }, { __index = wish.async })
 
+        local fs_proxy = setmetatable({
+            watch = function(path, opts, callback)
+                local watch = wish.fs.watch(path, opts, guard(callback))
+                table.insert(state.watches, watch)
+                return watch
+            end,
+        }, { __index = wish.fs })
+
+        local net_proxy = setmetatable({
+            listen_unix = function(path, on_conn)
+                local listener = wish.net.listen_unix(path, guard(on_conn))
+                table.insert(state.listeners, listener)
+                return listener
+            end,
+        }, { __index = wish.net })
+
         -- Create the main wish proxy
         local proxy
         proxy = setmetatable({
             async = async_proxy,
+            fs = fs_proxy,
+            net = net_proxy,
 
             add_event_callback = function(event, callback)
                 if event == 'init' and init then
@@ -112,13 +275,17 @@ return function(plugin_fn)
                     return
                 end
 
-                local id = wish.add_event_callback(event, callback)
+                local id = wish.add_event_callback(event, guard(callback))
                 table.insert(state.event_callbacks, id)
                 return id
             end,
 
+            schedule = function(callback)
+                return wish.schedule(guard(callback))
+            end,
+
             add_render_callback = function(callback)
-                local id = wish.add_render_callback(callback)
+                local id = wish.add_render_callback(guard(callback))
                 table.insert(state.render_callbacks, id)
                 return id
             end,
@@ -130,7 +297,7 @@ return function(plugin_fn)
                     end
                     layer = state.plugin_keymap_layer
                 end
-                return wish.set_keymap(key, cb, layer)
+                return wish.set_keymap(key, guard(cb), layer)
             end,
 
             add_keymap_layer = function(...)
@@ -190,3 +357,10 @@ return function(plugin_fn)
 
     return plugin_obj
 end
+
+-- wish.plugin(plugin_fn, name)
+return setmetatable(M, {
+    __call = function(_, ...)
+        return M.new(...)
+    end,
+})
@@ -0,0 +1,304 @@
+-- declarative plugin loading
+--
+-- wish.plugins.setup{
+--     -- a module name, looked up in $XDG_DATA_HOME/wish/lua first
+--     {'wish.completion', opts = {keybinds = {['<tab>'] = 'start'}}},
+--     {
+--         name = 'suggest',
+--         -- a module name or a path to a file or a directory with an init.lua
+--         source = 'wish.extras.autosuggestions',
+--         enabled = true,
+--         opts = {},
+--         -- these get loaded first
+--         deps = {'wish.completion'},
+--         -- load on the first of these instead of straight away
+--         -- keys run the plugin method named in opts.keybinds (or given here) once loaded
+--         lazy = {events = {'buffer_change'}, keys = {'<c-f>'}},
+--     },
+-- }
+--
+-- a plugin source returns either a wish.plugin object or the function to pass to wish.plugin
+local M = {}
+
+local PLUGINS = {}
+-- names in the order they were set up
+local ORDER = {}
+
+local function is_path(source)
+    return source:find('/') or source:find('%.lua$')
+end
+
+local function load_source(source)
+    if not is_path(source) then
+        return require(source)
+    end
+
+    source = source:gsub('^~/', (os.getenv('HOME') or '~') .. '/')
+    local file = io.open(source .. '/init.lua', 'r')
+    if file then
+        file:close()
+        source = source .. '/init.lua'
+    end
+    return dofile(source)
+end
+
+local function unload_source(source)
+    if not is_path(source) then
+        package.loaded[source] = nil
+    end
+end
+
+local function get(name)
+    local plugin = PLUGINS[name]
+    if not plugin then
+        error('unknown plugin: ' .. tostring(name), 3)
+    end
+    return plugin
+end
+
+local function clear_triggers(plugin)
+    for _, id in ipairs(plugin.triggers.events) do
+        wish.remove_event_callback(id)
+    end
+    plugin.triggers.events = {}
+    if plugin.triggers.layer then
+        wish.del_keymap_layer(plugin.triggers.layer)
+        plugin.triggers.layer = nil
+    end
+end
+
+-- enables the plugin, and its deps before that
+-- returns whether it is loaded
+local function load(plugin, loading)
+    if plugin.loaded then
+        -- it may have been disabled for failing too often, in which case enable it again
+        if not plugin.obj.is_enabled or plugin.obj.is_enabled() then
+            return true
+        end
+        plugin.loaded = false
+    end
+    -- from a previous attempt
+    plugin.error = nil
+    -- only the plugins currently being loaded, so a shared dep is not a cycle
+    loading = loading or {}
+    if loading[plugin.name] then
+        plugin.error = 'has a dependency cycle'
+        wish.log.error('plugin ' .. plugin.name .. ' ' .. plugin.error)
+        return false
+    end
+
+    loading[plugin.name] = true
+    for _, dep in ipairs(plugin.spec.deps or {}) do
+        local dep_plugin = PLUGINS[dep]
+        if not dep_plugin then
+            plugin.error = 'missing dependency: ' .. dep
+        elseif dep_plugin.spec.enabled == false then
+            plugin.error = 'dependency is disabled: ' .. dep
+        elseif not load(dep_plugin, loading) then
+            plugin.error = 'dependency failed to load: ' .. dep
+        end
+        if plugin.error then
+            break
+        end
+    end
+    loading[plugin.name] = nil
+
+    if plugin.error then
+        wish.log.error('plugin ' .. plugin.name .. ' ' .. plugin.error)
+        return false
+    end
+
+    clear_triggers(plugin)
+
+    local start = wish.time()
+    local ok, err = pcall(function()
+        local obj = load_source(plugin.spec.source or plugin.name)
+        if type(obj) == 'function' then
+            obj = wish.plugin(obj, plugin.name)
+        end
+        if type(obj) ~= 'table' or type(obj.enable) ~= 'function' then
+            error(plugin.name .. ' did not return a plugin', 0)
+        end
+        if obj.set_name then
+            obj.set_name(plugin.name)
+        end
+        plugin.obj = obj
+        obj.enable(plugin.spec.opts or {})
+    end)
+    plugin.load_time = wish.time() - start
+
+    if ok then
+        plugin.loaded = true
+        plugin.error = nil
+        wish.log.debug(string.format('loaded plugin %s in %.1fms', plugin.name, plugin.load_time * 1000))
+    else
+        plugin.error = tostring(err)
+        wish.log.error('failed to load plugin ' .. plugin.name .. ': ' .. plugin.error)
+    end
+    return ok
+end
+
+local function add_triggers(plugin)
+    local lazy = plugin.spec.lazy
+    if type(lazy) ~= 'table' then
+        -- only loaded on demand
+        return
+    end
+
+    for _, event in ipairs(lazy.events or {}) do
+        local id = wish.add_event_callback(event, function()
+            load(plugin)
+        end)
+        table.insert(plugin.triggers.events, id)
+    end
+
+    if lazy.keys and next(lazy.keys) then
+        plugin.triggers.layer = wish.add_keymap_layer()
+        local keybinds = (plugin.spec.opts or {}).keybinds or {}
+        for k, v in pairs(lazy.keys) do
+            -- either a list of keys or a map of key to plugin method
+            local key, method = k, v
+            if type(k) == 'number' then
+                key, method = v, keybinds[v]
+            end
+            wish.set_keymap(key, function()
+                if load(plugin) and method and plugin.obj[method] then
+                    return plugin.obj[method]()
+                end
+            end, plugin.triggers.layer)
+        end
+    end
+end
+
+local function unload(plugin)
+    clear_triggers(plugin)
+    if plugin.loaded then
+        plugin.obj.disable()
+        plugin.loaded = false
+    end
+end
+
+-- plugins that (indirectly) depend on this one and are loaded, in load order
+local function get_loaded_dependents(name)
+    local dependents = {}
+    local found = {[name] = true}
+    local changed = true
+    while changed do
+        changed = false
+        for _, other in ipairs(ORDER) do
+            local plugin = PLUGINS[other]
+            if not found[other] and plugin.loaded then
+                for _, dep in ipairs(plugin.spec.deps or {}) do
+                    if found[dep] then
+                        found[other] = true
+                        table.insert(dependents, plugin)
+                        changed = true
+                        break
+                    end
+                end
+            end
+        end
+    end
+    table.sort(dependents, function(a, b) return a.order < b.order end)
+    return dependents
+end
+
+function M.setup(specs)
+    local new = {}
+    for _, spec in ipairs(specs) do
+        local name = spec.name or spec[1]
+        if type(name) ~= 'string' then
+            error('plugin spec needs a name', 2)
+        end
+
+        local plugin = PLUGINS[name]
+        if plugin then
+            -- being redefined
+            unload(plugin)
+        else
+            table.insert(ORDER, name)
+            plugin = {name = name, order = #ORDER}
+            PLUGINS[name] = plugin
+        end
+        plugin.spec = spec
+        plugin.triggers = {events = {}}
+        plugin.error = nil
+        table.insert(new, plugin)
+    end
+
+    for _, plugin in ipairs(new) do
+        if plugin.spec.enabled ~= false then
+            if plugin.spec.lazy then
+                add_triggers(plugin)
+            else
+                load(plugin)
+            end
+        end
+    end
+end
+
+-- load a plugin now instead of waiting for its triggers
+function M.load(name)
+    return load(get(name))
+end
+
+function M.unload(name)
+    unload(get(name))
+end
+
+-- disable then enable the plugin again, picking up any changes to its source
+-- anything depending on it is reloaded too
+-- can be called as wish.plugins.reload(name) or wish.plugins:reload(name)
+function M.reload(...)
+    local name = ...
+    if name == M then
+        name = select(2, ...)
+    end
+    local plugin = get(name)
+
+    local dependents = get_loaded_dependents(name)
+    for i = #dependents, 1, -1 do
+        unload(dependents[i])
+    end
+    unload(plugin)
+    unload_source(plugin.spec.source or plugin.name)
+
+    local ok = load(plugin)
+    for _, dependent in ipairs(dependents) do
+        load(dependent)
+    end
+    return ok
+end
+
+function M.get(name)
+    return get(name).obj
+end
+
+-- state of each plugin in the order they were set up
+function M.list()
+    local list = {}
+    for _, name in ipairs(ORDER) do
+        local plugin = PLUGINS[name]
+        local loaded = plugin.loaded or false
+        local errors, failure = 0, nil
+        if loaded and plugin.obj.get_errors then
+            errors, failure = plugin.obj.get_errors()
+            -- it may have been disabled for failing too often
+            loaded = plugin.obj.is_enabled()
+        end
+        table.insert(list, {
+            name = name,
+            enabled = plugin.spec.enabled ~= false,
+            loaded = loaded,
+            lazy = plugin.spec.lazy and true or false,
+            -- in seconds
+            load_time = plugin.load_time,
+            -- failed callbacks
+            errors = errors,
+            error = plugin.error or failure,
+        })
+    end
+    return list
+end
+
+return M
@@ -0,0 +1,229 @@
+-- prompt built from segments that are computed asynchronously
+--
+-- wish.prompt.segment{
+--     name = 'git',
+--     compute = function() return wish.cmd('git', 'branch', '--show-current') end,
+--     -- skip recomputing while this returns the same thing
+--     cache_key = wish.get_cwd,
+--     timeout = 2,
+--     -- shown until the first result comes in
+--     placeholder = '...',
+-- }
+-- wish.prompt.set{
+--     -- a list of lines made of strings, text parts and segments
+--     prompt = { {{segment = 'cwd'}, ' ', {segment = 'git', fg = 'magenta'}}, {'> '} },
+--     rprompt = { {{segment = 'kube'}} },
+-- }
+--
+-- segments show their placeholder (or their last value) straight away
+-- and each one is its own message which is updated as it finishes
+-- anything still running from the previous prompt is cancelled
+local M = {}
+
+M.DEFAULT_TIMEOUT = 5
+
+local SEGMENTS = {}
+local FORMAT = nil
+local generation = 0
+local tasks = {}
+-- the messages making up the prompt and rprompt
+local built = {}
+
+function M.segment(opts)
+    if not opts.name then
+        error('prompt segment needs a name', 2)
+    end
+    if not opts.compute then
+        error('prompt segment needs a compute function', 2)
+    end
+    local old = SEGMENTS[opts.name]
+    SEGMENTS[opts.name] = {
+        compute = opts.compute,
+        cache_key = opts.cache_key,
+        timeout = opts.timeout or M.DEFAULT_TIMEOUT,
+        placeholder = opts.placeholder,
+        -- keep the value around if this is just being redefined
+        value = old and old.value,
+        key = nil,
+    }
+end
+
+function M.remove_segment(name)
+    SEGMENTS[name] = nil
+end
+
+-- turn a segment value into text parts
+local function value_to_parts(value, style)
+    if value == nil or value == '' then
+        return {}
+    elseif type(value) == 'table' then
+        return value
+    end
+    return { wish.table.merge(wish.table.copy(style), {text = tostring(value)}) }
+end
+
+local function item_to_parts(item)
+    if type(item) == 'string' then
+        return { {text = item} }
+    elseif item.segment then
+        local segment = SEGMENTS[item.segment]
+        local value = segment and segment.value
+        if value == nil and segment then
+            value = segment.placeholder
+        end
+        local style = wish.table.copy(item)
+        style.segment = nil
+        return value_to_parts(value, style)
+    end
+    return { item }
+end
+
+local function parts_width(parts)
+    local width = 0
+    for _, part in ipairs(parts) do
+        width = width + wish.str.width(tostring(part.text or ''))
+    end
+    return width
+end
+
+-- each item is its own message so that only the ones that changed get updated
+local function render_item(child)
+    local parts = item_to_parts(child.item)
+    local repr = wish.repr(parts)
+    if child.repr == repr then
+        return
+    end
+    child.repr = repr
+    -- the items in a line are exactly as wide as their text
+    local width = parts_width(parts)
+    child.id = wish.set_message{
+        id = child.id,
+        persist = true,
+        hidden = width == 0,
+        min_width = width,
+        max_width = width,
+        contents = {text = parts},
+    }
+end
+
+-- a vertical layout of lines, each one a horizontal layout of items
+local function build(setter, lines)
+    local prompt = {lines = {}, children = {}}
+    for _, line in ipairs(lines) do
+        if #line == 0 then
+            -- keep the empty line
+            line = {' '}
+        end
+        local ids = {}
+        for _, item in ipairs(line) do
+            local child = {item = item}
+            render_item(child)
+            table.insert(prompt.children, child)
+            table.insert(ids, child.id)
+        end
+        table.insert(prompt.lines, wish.set_message{persist = true, direction = 'horizontal', children = ids})
+    end
+    setter{direction = 'vertical', children = prompt.lines}
+    return prompt
+end
+
+local function destroy(prompt)
+    -- these may already be gone, e.g. after clearing all messages
+    for _, id in ipairs(prompt.lines) do
+        pcall(wish.remove_message, id)
+    end
+    for _, child in ipairs(prompt.children) do
+        pcall(wish.remove_message, child.id)
+    end
+end
+
+function M.render()
+    for _, prompt in pairs(built) do
+        for _, child in ipairs(prompt.children) do
+            render_item(child)
+        end
+    end
+end
+
+local function used_segments()
+    local names = {}
+    for _, lines in pairs{FORMAT.prompt or {}, FORMAT.rprompt or {}} do
+        for _, line in ipairs(lines) do
+            for _, item in ipairs(line) do
+                if type(item) == 'table' and item.segment and SEGMENTS[item.segment] then
+                    names[item.segment] = true
+                end
+            end
+        end
+    end
+    return names
+end
+
+function M.cancel()
+    for i = #tasks, 1, -1 do
+        tasks[i]:cancel()
+        tasks[i] = nil
+    end
+end
+
+function M.refresh()
+    if not FORMAT then
+        return
+    end
+
+    -- anything from the previous prompt is stale
+    M.cancel()
+    generation = generation + 1
+    local current = generation
+
+    for name in pairs(used_segments()) do
+        local segment = SEGMENTS[name]
+        local key = segment.cache_key and segment.cache_key()
+        if key == nil or key ~= segment.key then
+            table.insert(tasks, wish.async.spawn_task(function()
+                local ok, value = pcall(wish.async.timeout, segment.timeout, segment.compute)
+                if current ~= generation or SEGMENTS[name] ~= segment then
+                    return
+                end
+                if ok then
+                    segment.value = value
+                    segment.key = key
+                else
+                    wish.log.debug('prompt segment ' .. name .. ' failed: ' .. tostring(value))
+                    segment.value = nil
+                    segment.key = nil
+                end
+                M.render()
+            end))
+        end
+    end
+
+    M.render()
+end
+
+function M.set(format)
+    FORMAT = format
+    M.cancel()
+
+    local old = built
+    built = {}
+    for key, setter in pairs{prompt = wish.set_prompt, rprompt = wish.set_rprompt} do
+        if format and format[key] then
+            built[key] = build(setter, format[key])
+        elseif old[key] or not format then
+            setter(nil)
+        end
+    end
+    -- only once nothing uses them any more
+    for _, prompt in pairs(old) do
+        destroy(prompt)
+    end
+
+    if format then
+        M.refresh()
+    end
+end
+
+wish.add_event_callback('precmd', M.refresh)
+
+return M
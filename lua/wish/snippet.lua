@@ -0,0 +1,368 @@
+-- LSP/VSCode style snippets
+-- $1, ${2:default}, ${3|one,two|} and $0 for the final cursor position
+-- repeating a tabstop mirrors it, e.g. ${1:name} ... $1
+-- other $ are left alone since they are most likely shell variables
+local M = {}
+
+M.keybinds = {
+    next = '<tab>',
+    prev = '<s-tab>',
+    choice = '<c-n>',
+}
+
+M.style = {
+    bg = 'black',
+    underline = true,
+}
+
+local PRIORITY = 1000
+local NAMESPACE = nil
+local session = nil
+
+local function unescape(str, chars)
+    return (str:gsub('\\(.)', function(c)
+        if chars:find(c, 1, true) then
+            return c
+        end
+    end))
+end
+
+local function split_choices(str)
+    local choices = {}
+    local current = ''
+    local i = 1
+    while i <= #str do
+        local c = str:sub(i, i)
+        if c == '\\' and i < #str then
+            current = current .. str:sub(i, i + 1)
+            i = i + 1
+        elseif c == ',' then
+            table.insert(choices, unescape(current, ',|\\'))
+            current = ''
+        else
+            current = current .. c
+        end
+        i = i + 1
+    end
+    table.insert(choices, unescape(current, ',|\\'))
+    return choices
+end
+
+-- defaults maps a tabstop to the text of its placeholder, so that mirrors can be filled in
+local function parse(body, defaults)
+    local output = {}
+    local len = 0
+    local fields = {}
+    local pos = 1
+
+    local function emit(str)
+        table.insert(output, str)
+        len = len + #str
+    end
+
+    local function add_field(index, start, choices)
+        table.insert(fields, {index = tonumber(index), start = start, finish = len, choices = choices})
+    end
+
+    local parse_until
+
+    local function parse_dollar()
+        local index = body:match('^%$(%d+)', pos) or body:match('^%${(%d+)}', pos)
+        if index then
+            pos = pos + (body:sub(pos + 1, pos + 1) == '{' and #index + 3 or #index + 1)
+            local start = len
+            emit(defaults[tonumber(index)] or '')
+            add_field(index, start)
+            return
+        end
+
+        index = body:match('^%${(%d+):', pos)
+        if index then
+            pos = pos + #index + 3
+            local start = len
+            if not parse_until('}') then
+                error('unterminated placeholder in snippet: ' .. body, 0)
+            end
+            pos = pos + 1
+            add_field(index, start)
+            defaults[tonumber(index)] = defaults[tonumber(index)] or table.concat(output):sub(start + 1)
+            return
+        end
+
+        local choices
+        index, choices = body:match('^%${(%d+)|(.-[^\\])|}', pos)
+        if index then
+            pos = pos + #index + #choices + 5
+            choices = split_choices(choices)
+            local start = len
+            emit(choices[1])
+            add_field(index, start, choices)
+            defaults[tonumber(index)] = defaults[tonumber(index)] or choices[1]
+            return
+        end
+
+        emit('$')
+        pos = pos + 1
+    end
+
+    function parse_until(stop)
+        while pos <= #body do
+            local c = body:sub(pos, pos)
+            if c == '\\' and pos < #body then
+                local next = body:sub(pos + 1, pos + 1)
+                emit(next:find('[$}\\]') and next or c .. next)
+                pos = pos + 2
+            elseif c == stop then
+                return true
+            elseif c == '$' then
+                parse_dollar()
+            else
+                emit(c)
+                pos = pos + 1
+            end
+        end
+        return stop == nil
+    end
+
+    parse_until(nil)
+    return table.concat(output), fields
+end
+
+function M.parse(body)
+    local defaults = {}
+    -- first pass is only to find the defaults
+    parse(body, defaults)
+    return parse(body, defaults)
+end
+
+-- the buffer cursor counts graphemes from 1, marks count bytes
+-- these convert between the cursor and a byte offset from 0
+local function cursor_to_byte(str, cursor)
+    local graphemes = wish.str.graphemes(str)
+    local bytes = 0
+    for i = 1, math.min(cursor - 1, #graphemes) do
+        bytes = bytes + #graphemes[i]
+    end
+    return bytes
+end
+
+local function byte_to_cursor(str, pos)
+    return #wish.str.graphemes(str:sub(1, pos)) + 1
+end
+
+-- replace start..finish (inclusive, 1-based bytes) in the buffer
+local function replace(start, finish, text, cursor)
+    local buffer, old_cursor = wish.get_buffer()
+    if not cursor then
+        cursor = cursor_to_byte(buffer, old_cursor)
+        if cursor >= finish then
+            cursor = cursor + #text - (finish - start + 1)
+        end
+    end
+    local new = buffer:sub(1, start - 1) .. text .. buffer:sub(finish + 1)
+    wish.set_buffer(new, byte_to_cursor(new, cursor))
+end
+
+local function current_field()
+    return session and session.fields[session.order[session.pos]]
+end
+
+local function highlight()
+    wish.clear_buf_highlights(NAMESPACE)
+    local field = current_field()
+    if not field then
+        return
+    end
+    for i = 1, #field.marks do
+        local mark = wish.get_mark(field.marks[i])
+        if mark and mark.finish >= mark.start then
+            wish.add_buf_highlight(wish.table.merge(wish.table.copy(M.style), {
+                start = mark.start,
+                finish = mark.finish,
+                namespace = NAMESPACE,
+                priority = PRIORITY,
+            }))
+        end
+    end
+end
+
+-- copy the text of the first occurrence of the field to the others
+local function sync_mirrors(field)
+    local primary = wish.get_mark(field.marks[1])
+    if not primary then
+        return
+    end
+    local text = wish.get_buffer():sub(primary.start, primary.finish)
+    for i = 2, #field.marks do
+        local mark = wish.get_mark(field.marks[i])
+        if mark and wish.get_buffer():sub(mark.start, mark.finish) ~= text then
+            replace(mark.start, mark.finish, text)
+        end
+    end
+end
+
+local function set_field_text(field, text)
+    local mark = wish.get_mark(field.marks[1])
+    if mark then
+        replace(mark.start, mark.finish, text, mark.start - 1 + #text)
+        sync_mirrors(field)
+    end
+end
+
+local function with_sync(func)
+    session.syncing = true
+    local ok, err = pcall(wish.undo_join, func)
+    if session then
+        session.syncing = false
+    end
+    if not ok then
+        error(err, 0)
+    end
+end
+
+function M.is_active()
+    return session ~= nil
+end
+
+function M.stop()
+    if not session then
+        return
+    end
+    wish.del_keymap_layer(session.keymap_layer)
+    wish.clear_marks(NAMESPACE)
+    wish.clear_buf_highlights(NAMESPACE)
+    session = nil
+end
+
+function M.jump(step)
+    if not session then
+        return
+    end
+    session.pos = math.max(1, session.pos + step)
+
+    local index = session.order[session.pos]
+    local field = session.fields[index]
+    local mark = field and wish.get_mark(field.marks[1])
+    if not mark then
+        M.stop()
+        return
+    end
+
+    local buffer = wish.get_buffer()
+    wish.set_cursor(byte_to_cursor(buffer, mark.finish))
+
+    if index == 0 then
+        -- reached the end
+        M.stop()
+        return
+    end
+
+    -- typing straight away replaces the placeholder
+    session.pristine = buffer:sub(mark.start, mark.finish)
+    highlight()
+end
+
+function M.next_choice()
+    local field = current_field()
+    if not field or not field.choices then
+        return
+    end
+    field.choice = field.choice % #field.choices + 1
+    session.pristine = nil
+    with_sync(function()
+        set_field_text(field, field.choices[field.choice])
+    end)
+    highlight()
+end
+
+function M.expand(body)
+    M.stop()
+    NAMESPACE = NAMESPACE or wish.add_buf_highlight_namespace()
+
+    local text, fields = M.parse(body)
+    local buffer, cursor = wish.get_buffer()
+    local offset = cursor_to_byte(buffer, cursor)
+
+    wish.undo_join(function()
+        wish.insert_at_cursor(text)
+    end)
+
+    local new_session = {
+        fields = {},
+        order = {},
+        pos = 0,
+    }
+    for i = 1, #fields do
+        local f = fields[i]
+        local field = new_session.fields[f.index]
+        if not field then
+            field = {marks = {}, choice = 1}
+            new_session.fields[f.index] = field
+            if f.index ~= 0 then
+                table.insert(new_session.order, f.index)
+            end
+        end
+        field.choices = field.choices or f.choices
+        local mark = wish.set_mark{
+            start = offset + f.start + 1,
+            finish = offset + f.finish,
+            namespace = NAMESPACE,
+            -- typing at either edge of a field grows it
+            gravity = 'inner',
+        }
+        -- the cursor goes to the first one and the rest mirror it
+        table.insert(field.marks, mark)
+    end
+
+    -- without $0 the cursor ends up after the snippet
+    if not new_session.fields[0] then
+        local mark = wish.set_mark{start = offset + #text + 1, namespace = NAMESPACE}
+        new_session.fields[0] = {marks = {mark}}
+    end
+    table.sort(new_session.order)
+    table.insert(new_session.order, 0)
+
+    new_session.keymap_layer = wish.add_keymap_layer()
+    wish.set_keymap(M.keybinds.next, function() M.jump(1) end, new_session.keymap_layer)
+    wish.set_keymap(M.keybinds.prev, function() M.jump(-1) end, new_session.keymap_layer)
+    wish.set_keymap(M.keybinds.choice, M.next_choice, new_session.keymap_layer)
+
+    session = new_session
+    M.jump(1)
+end
+
+wish.add_event_callback('buffer_change', function()
+    local field = current_field()
+    if not field or session.syncing then
+        return
+    end
+
+    local primary = wish.get_mark(field.marks[1])
+    if not primary then
+        M.stop()
+        return
+    end
+
+    with_sync(function()
+        local pristine = session.pristine
+        session.pristine = nil
+        local text = wish.get_buffer():sub(primary.start, primary.finish)
+        if pristine and pristine ~= '' and text ~= pristine then
+            if text:sub(1, #pristine) == pristine then
+                -- typed at the end, replace the placeholder with it
+                set_field_text(field, text:sub(#pristine + 1))
+                return
+            elseif pristine:sub(1, #text) == text then
+                -- deleted from the end, clear the placeholder
+                set_field_text(field, '')
+                return
+            end
+        end
+        sync_mirrors(field)
+    end)
+    highlight()
+end)
+
+wish.add_event_callback('accept_line', M.stop)
+
+return M
@@ -1,5 +1,3 @@
-local QUERY = require('wish.syntax-query')
-
 local RULES = {
     { {hl='command', kind='|STRING'}, {mod='*'} },
     -- comments
@@ -60,75 +58,26 @@ local RULES = {
 return wish.plugin(function(wish, opts, plugin)
 
     local NAMESPACE = wish.add_buf_highlight_namespace()
+    local QUERY = wish.compile_syntax_query(RULES)
 
-    local function apply_highlight_matcher(matcher, token, str, highlights, priority)
-        if not matcher.hl then
-            return
-        end
+    local UNKNOWN_NAMESPACE = opts.highlight_unknown_commands and wish.add_buf_highlight_namespace()
 
-        if matcher.hlregex then
-            if type(matcher.hlregex) == 'string' then
-                matcher.hlregex = wish.regex(matcher.hlregex)
-            end
+    -- the parse is cached on the buffer and this only fires when it actually changes
+    wish.add_event_callback('parse_change', function()
+        wish.apply_syntax_query(QUERY, NAMESPACE)
 
-            local tokstr = string.sub(str, token.start, token.finish)
-            local captures = matcher.hlregex:captures_all(tokstr)
-            for _, capture in ipairs(captures) do
-                local index = capture[2] or capture[1]
-                local hl = wish.table.copy(wish.style[matcher.hl])
-                hl.start = token.start + index[1] - 1
-                hl.finish = token.start + index[2] - 1
-                hl.namespace = NAMESPACE
-                hl.priority = priority
-                table.insert(highlights, hl)
+        if UNKNOWN_NAMESPACE then
+            wish.clear_buf_highlights(UNKNOWN_NAMESPACE)
+            for _, cmd in ipairs(wish.parse_commands()) do
+                if cmd.command and wish.resolve_command(cmd.command.value).type == 'none' then
+                    wish.add_buf_highlight(wish.table.merge({}, wish.style.error, {
+                        start = cmd.command.start,
+                        finish = cmd.command.finish,
+                        namespace = UNKNOWN_NAMESPACE,
+                        priority = math.huge,
+                    }))
+                end
             end
-
-        else
-            local hl = wish.table.copy(wish.style[matcher.hl])
-            hl.start = token.start
-            hl.finish = token.finish
-            hl.namespace = NAMESPACE
-            hl.priority = priority
-            table.insert(highlights, hl)
-        end
-    end
-
-    local function apply_highlight_rules(rules, tokens, str, highlights, priority)
-        priority = priority or 0
-        highlights = highlights or {}
-        local rule_priority
-        local function callback(matches)
-            for i = 1, #matches do
-                apply_highlight_matcher(matches[i][1], matches[i][2], str, highlights, rule_priority)
-            end
-        end
-
-        for i = 1, #rules do
-            rule_priority = (priority + (rules[i].priority or 0)) * #rules * 2 + i
-            QUERY.apply_seq(rules[i], tokens, str, callback)
-        end
-        for i = 1, #tokens do
-            if tokens[i].children then
-                apply_highlight_rules(rules, tokens[i].children, str, highlights, priority+1)
-            end
-        end
-        return highlights
-    end
-
-    QUERY.add_buffer_callback(function(tokens, str)
-        if not plugin.is_enabled then
-            return true
-        end
-
-        wish.clear_buf_highlights(NAMESPACE)
-
-        local highlights = apply_highlight_rules(RULES, tokens, str)
-        for i = 1, #highlights do
-            highlights[i].priority = highlights[i].priority + i / #highlights / 2
-        end
-        wish.table.sort_by(highlights, 'priority')
-        for i = 1, #highlights do
-            wish.add_buf_highlight(highlights[i])
         end
     end)
 
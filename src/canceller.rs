@@ -1,8 +1,16 @@
 use tokio::sync::{oneshot};
+use futures::future::{FutureExt, Shared};
 
-#[allow(dead_code)]
 pub struct Canceller(oneshot::Sender<()>);
 pub struct Cancellable(oneshot::Receiver<()>);
+#[derive(Clone)]
+pub struct SharedCancellable(Shared<oneshot::Receiver<()>>);
+
+impl Canceller {
+    pub fn cancel(self) {
+        let _ = self.0.send(());
+    }
+}
 
 impl Cancellable {
     pub async fn run<T, F: Future<Output=T>>(&mut self, f: F) -> Option<T> {
@@ -11,6 +19,51 @@ impl Cancellable {
             _ = &mut self.0 => None,
         )
     }
+
+    pub fn shared(self) -> SharedCancellable {
+        SharedCancellable(self.0.shared())
+    }
+}
+
+impl SharedCancellable {
+    pub async fn cancelled(&self) {
+        let _ = self.0.clone().await;
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.clone().now_or_never().is_some()
+    }
+
+    pub async fn run<T, F: Future<Output=T>>(self, f: F) -> Option<T> {
+        tokio::select!(
+            result = f => Some(result),
+            () = self.cancelled() => None,
+        )
+    }
+}
+
+// cancels everything that was started in it at once
+pub struct Scope {
+    canceller: Canceller,
+    cancellable: SharedCancellable,
+}
+
+impl Default for Scope {
+    fn default() -> Self {
+        let (canceller, cancellable) = new();
+        Self{ canceller, cancellable: cancellable.shared() }
+    }
+}
+
+impl Scope {
+
+    pub fn get(&self) -> SharedCancellable {
+        self.cancellable.clone()
+    }
+
+    pub fn cancel(self) {
+        self.canceller.cancel();
+    }
 }
 
 pub fn new() -> (Canceller, Cancellable) {
@@ -47,6 +47,17 @@ impl KeyHandler<'_> {
             return Ok(Some(result));
         }
 
+        // space and enter expand abbreviations
+        if let Event::Key(KeyEvent{ key: key @ (Key::Char(' ') | Key::Enter), modifiers }) = event
+            && modifiers.difference(Modifiers::SHIFT).is_empty()
+            && let Some(has_marker) = self.expand_abbreviation().await?
+            && has_marker
+            && *key != Key::Enter
+        {
+            // the cursor has been put somewhere specific, so don't insert the space
+            return Ok(Some(Action::Done{exit: false}));
+        }
+
         if buf.len() == 1 {
             // zsh doesn't run widgets if eof
             let is_eof = {
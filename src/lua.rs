@@ -3,6 +3,9 @@ use std::cell::BorrowError;
 use std::sync::atomic::{AtomicPtr, Ordering, AtomicUsize};
 use crate::ui::{Ui, WeakUi};
 use std::cell::RefCell;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 use anyhow::Result;
 use mlua::prelude::*;
 mod api;
@@ -14,13 +17,17 @@ pub use api::{
     init_lua,
     keybind::invoke_keybind_callback,
     tui::EphemeralStyleOptions,
+    tui::make_transient_prompt,
     KeybindMapping,
     EventCallbacks,
+    EventType,
 };
 
 // i must use atomics here as these are used in signal handlers
 static LUA_PTR: AtomicPtr<mlua::ffi::lua_State> = AtomicPtr::new(std::ptr::null_mut());
 static LUA_LEVEL: AtomicUsize = AtomicUsize::new(0);
+// what was there before any of the lua config ran
+const UNLOAD_CONFIG_KEY: &str = "wish_unload_config";
 const LUA_HOOK_MASK: c_int = mlua::ffi::LUA_MASKCALL | mlua::ffi::LUA_MASKRET | mlua::ffi::LUA_MASKLINE | mlua::ffi::LUA_MASKCOUNT;
 
 pub fn lua_error<S: ToString>(msg: S) -> mlua::Error {
@@ -34,6 +41,68 @@ pub async fn call_lua_fn<T: IntoLuaMulti + 'static, R: FromLuaMulti>(callback: &
     result
 }
 
+// how often to check if lua code has run out of time
+const TIME_LIMIT_HOOK_COUNT: c_int = 1000;
+
+struct TimeLimit {
+    // none if it is too far away to represent
+    deadline: Option<Instant>,
+    expired: bool,
+}
+
+thread_local! {
+    // lua code that is currently running under a time limit, innermost last
+    static TIME_LIMITS: RefCell<Vec<TimeLimit>> = const { RefCell::new(vec![]) };
+}
+
+// only counts the time spent running, not while waiting on the lua code
+struct WithTimeLimit<F> {
+    inner: Pin<Box<F>>,
+    limit: Duration,
+    expired: bool,
+}
+
+impl<F: Future> Future for WithTimeLimit<F> {
+    type Output = (F::Output, bool);
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let lua = LUA_PTR.load(Ordering::Relaxed);
+        let deadline = Instant::now().checked_add(self.limit);
+        TIME_LIMITS.with_borrow_mut(|limits| limits.push(TimeLimit{ deadline, expired: false }));
+        if !lua.is_null() {
+            unsafe { set_time_limit_hook(lua) };
+        }
+
+        let result = self.inner.as_mut().poll(cx);
+
+        let (limit, empty) = TIME_LIMITS.with_borrow_mut(|limits| (limits.pop(), limits.is_empty()));
+        self.expired |= limit.is_some_and(|limit| limit.expired);
+        if empty && !lua.is_null() {
+            unsafe {
+                if mlua::ffi::lua_gethook(lua).is_some_and(|hook| std::ptr::fn_addr_eq(hook, lua_time_limit_hook as mlua::ffi::lua_Hook)) {
+                    mlua::ffi::lua_sethook(lua, None, 0, 0);
+                }
+            }
+        }
+
+        let expired = self.expired;
+        result.map(|result| (result, expired))
+    }
+}
+
+// also returns whether the time limit was hit
+pub async fn call_lua_fn_with_time_limit<T: IntoLuaMulti + 'static, R: FromLuaMulti>(
+    callback: &mlua::Function,
+    arg: T,
+    limit: Duration,
+) -> (LuaResult<R>, bool) {
+    WithTimeLimit {
+        inner: Box::pin(call_lua_fn(callback, arg)),
+        limit,
+        expired: false,
+    }.await
+}
+
 pub struct LuaWrapper {
     inner: Lua,
     pub api: LuaTable,
@@ -77,14 +146,104 @@ impl LuaWrapper {
             local xdg_data = os.getenv('XDG_DATA_HOME')
             local home = os.getenv('HOME')
             local base = xdg_data or (home and home .. '/.local/share')
-            local wish_path = base and (base .. '/wish/lua/?.lua;') or ''
+            local wish_path = base and (base .. '/wish/lua/?.lua;' .. base .. '/wish/lua/?/init.lua;') or ''
             local p = (';' .. package.path .. ';'):gsub(';%./%?%.lua;', ''):gsub('^;', ''):gsub(';$', '')
             package.path = wish_path .. p
         ").exec()?;
+
+        // the time limit error must not be caught by the code that ran out of time
+        let expired = self.inner.create_function(|_, ()| Ok(time_limit_expired()))?;
+        self.inner.load(/*lua*/ r"
+            local expired = ...
+            local pcall, xpcall = pcall, xpcall
+            local function check(ok, ...)
+                if not ok and expired() then
+                    error('time limit exceeded', 0)
+                end
+                return ok, ...
+            end
+            _G.pcall = function(...) return check(pcall(...)) end
+            _G.xpcall = function(...) return check(xpcall(...)) end
+        ").call::<()>(expired)?;
+
+        // remember what is builtin so reloading can get rid of everything else
+        // this copies the globals and the tables in them (e.g. string, wish, wish.fs) but no deeper
+        // the builtins are captured up front in case the config replaces them
+        let unload: LuaFunction = self.inner.load(/*lua*/ r"
+            local pairs, type, wish = pairs, type, wish
+            local loaded = package.loaded
+
+            local modules = {}
+            for name in pairs(loaded) do
+                modules[name] = true
+            end
+
+            local tables = {}
+            local function snapshot(tbl)
+                if tables[tbl] or tbl == loaded then
+                    return
+                end
+                local copy = {}
+                for k, v in pairs(tbl) do
+                    copy[k] = v
+                end
+                tables[tbl] = copy
+            end
+            snapshot(_G)
+            for _, v in pairs(_G) do
+                if type(v) == 'table' then
+                    snapshot(v)
+                end
+            end
+            for _, v in pairs(wish) do
+                if type(v) == 'table' then
+                    snapshot(v)
+                end
+            end
+
+            return function()
+                local plugin = loaded['wish.plugin']
+                if plugin and plugin.disable_all then
+                    plugin.disable_all()
+                end
+                for name in pairs(loaded) do
+                    if not modules[name] then
+                        loaded[name] = nil
+                    end
+                end
+                for tbl, copy in pairs(tables) do
+                    for k in pairs(tbl) do
+                        if copy[k] == nil then
+                            tbl[k] = nil
+                        end
+                    end
+                    for k, v in pairs(copy) do
+                        tbl[k] = v
+                    end
+                end
+            end
+        ").eval()?;
+        self.inner.set_named_registry_value(UNLOAD_CONFIG_KEY, unload)?;
+
         self.inner.load("require('wish')").exec()?;
         Ok(())
     }
 
+    // disable plugins, forget all modules loaded since init_lua
+    // and put back the globals and builtin tables as they were then
+    // this is not a brand new lua state: anything the config changed deeper inside the builtin tables,
+    // in metatables (e.g. the string one) or in upvalues of builtin functions is kept
+    pub async fn unload_config(&self) -> Result<()> {
+        let unload: LuaFunction = self.inner.named_registry_value(UNLOAD_CONFIG_KEY)?;
+        unload.call_async::<()>(()).await?;
+        Ok(())
+    }
+
+    pub async fn load_config(&self) -> Result<()> {
+        self.inner.load("require('wish')").exec_async().await?;
+        Ok(())
+    }
+
     pub fn make_fn<F, A, R>(&self, func: F) -> Result<LuaFunction>
     where
         F: Fn(&Ui, &Lua, A) -> Result<R> + 'static,
@@ -119,10 +278,10 @@ impl LuaWrapper {
         Ok(self.inner.create_async_function(move |lua, value| {
             let ui = ui.clone();
             let func = func.clone();
-            async move {
+            api::asyncio::run_cancellable(async move {
                 let ui = Self::try_upgrade_ui(&ui)?;
                 func(ui, lua, value).await.map_err(lua_error)
-            }
+            })
         })?)
     }
 
@@ -163,12 +322,48 @@ extern "C-unwind" fn lua_sigint_hook(lua: *mut mlua::ffi::lua_State, _ar: *mut m
         // keep interrupting lua so long as there is more
         if LUA_LEVEL.load(Ordering::Relaxed) <= 1 {
             mlua::ffi::lua_sethook(lua, None, LUA_HOOK_MASK, 1);
+            // put back any time limit that it replaced
+            if TIME_LIMITS.with_borrow(|limits| !limits.is_empty()) {
+                set_time_limit_hook(lua);
+            }
         }
         mlua::ffi::lua_pushliteral(lua, c"interrupted");
         mlua::ffi::lua_error(lua);
     }
 }
 
+// the sigint hook takes priority, it puts this back once it is done
+unsafe fn set_time_limit_hook(lua: *mut mlua::ffi::lua_State) {
+    unsafe {
+        if mlua::ffi::lua_gethook(lua).is_none() {
+            mlua::ffi::lua_sethook(lua, Some(lua_time_limit_hook), mlua::ffi::LUA_MASKCOUNT, TIME_LIMIT_HOOK_COUNT);
+        }
+    }
+}
+
+// whether the innermost time limit has been hit
+pub fn time_limit_expired() -> bool {
+    TIME_LIMITS.with_borrow(|limits| limits.last().is_some_and(|limit| limit.expired))
+}
+
+extern "C-unwind" fn lua_time_limit_hook(lua: *mut mlua::ffi::lua_State, _ar: *mut mlua::ffi::lua_Debug) {
+    let now = Instant::now();
+    let expired = TIME_LIMITS.with_borrow_mut(|limits| {
+        if let Some(limit) = limits.last_mut() && limit.deadline.is_some_and(|deadline| now >= deadline) {
+            limit.expired = true;
+            true
+        } else {
+            false
+        }
+    });
+    if expired {
+        unsafe {
+            mlua::ffi::lua_pushliteral(lua, c"time limit exceeded");
+            mlua::ffi::lua_error(lua);
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct FromLuaStr<T>(T);
 
@@ -16,14 +16,19 @@ mod events;
 pub mod tui;
 mod log;
 mod process;
-mod asyncio;
+pub mod asyncio;
+mod fs;
+mod net;
+mod remote;
 mod parser;
 mod variables;
 mod functions;
 mod regex;
+mod abbr;
+mod options;
 use crate::keybind::EventIndex;
 pub use keybind::KeybindMapping;
-pub use events::{EventCallbacks};
+pub use events::{EventCallbacks, EventType};
 
 auto_from_lua! {
     #[derive(Debug, Default)]
@@ -100,6 +105,84 @@ async fn redo_buffer(ui: Ui, _lua: Lua, (): ()) -> Result<()> {
     Ok(())
 }
 
+async fn undo_goto(ui: Ui, _lua: Lua, node: usize) -> Result<()> {
+    if ui.try_borrow_mut()?.buffer.undo_goto(node) {
+        ui.event_callbacks.buffer_change(&ui).await?;
+        ui.event_callbacks.buffer_cursor_move(&ui).await?;
+        ui.queue_draw();
+    }
+    Ok(())
+}
+
+async fn undo_time(ui: &Ui, seconds: f64, forward: bool) -> Result<()> {
+    let delta = std::time::Duration::try_from_secs_f64(seconds)?;
+    if ui.try_borrow_mut()?.buffer.undo_goto_time(delta, forward) {
+        ui.event_callbacks.buffer_change(ui).await?;
+        ui.event_callbacks.buffer_cursor_move(ui).await?;
+        ui.queue_draw();
+    }
+    Ok(())
+}
+
+async fn undo_earlier(ui: Ui, _lua: Lua, seconds: f64) -> Result<()> {
+    undo_time(&ui, seconds, false).await
+}
+
+async fn undo_later(ui: Ui, _lua: Lua, seconds: f64) -> Result<()> {
+    undo_time(&ui, seconds, true).await
+}
+
+fn undo_group_begin(ui: &Ui, _lua: &Lua, (): ()) -> Result<()> {
+    ui.try_borrow_mut()?.buffer.undo.group_begin();
+    Ok(())
+}
+
+fn undo_group_end(ui: &Ui, _lua: &Lua, (): ()) -> Result<()> {
+    ui.try_borrow_mut()?.buffer.undo.group_end();
+    Ok(())
+}
+
+// ends the group even if the function errors or is cancelled
+struct UndoGroupGuard(Ui);
+
+impl Drop for UndoGroupGuard {
+    fn drop(&mut self) {
+        if let Ok(mut ui) = self.0.try_borrow_mut() {
+            ui.buffer.undo.group_end();
+        }
+    }
+}
+
+// all edits made inside the function become one undo step
+async fn undo_join(ui: Ui, _lua: Lua, func: LuaFunction) -> Result<LuaMultiValue> {
+    ui.try_borrow_mut()?.buffer.undo.group_begin();
+    let _guard = UndoGroupGuard(ui);
+    Ok(crate::lua::call_lua_fn(&func, ()).await?)
+}
+
+fn get_undo_tree(ui: &Ui, lua: &Lua, (): ()) -> Result<LuaTable> {
+    let ui = ui.try_borrow()?;
+    let tree = &ui.buffer.undo;
+
+    // node ids start at 0 for the root, so nodes[id + 1] is the node with that id
+    let nodes = lua.create_table_with_capacity(tree.nodes.len(), 0)?;
+    for (id, node) in tree.nodes.iter().enumerate() {
+        let time = node.time.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs_f64();
+        let tbl = lua.create_table()?;
+        tbl.raw_set("id", id)?;
+        tbl.raw_set("parent", node.parent)?;
+        tbl.raw_set("children", node.children.clone())?;
+        tbl.raw_set("time", time)?;
+        tbl.raw_set("edits", node.edits.len())?;
+        nodes.raw_push(tbl)?;
+    }
+
+    let result = lua.create_table()?;
+    result.raw_set("current", tree.current)?;
+    result.raw_set("nodes", nodes)?;
+    Ok(result)
+}
+
 async fn accept_line(mut ui: Ui, _lua: Lua, (): ()) -> Result<bool> {
     ui.accept_line().await
 }
@@ -133,10 +216,29 @@ async fn exit(mut ui: Ui, _lua: Lua, code: Option<i32>) -> Result<()> {
     Ok(())
 }
 
+async fn reload(ui: Ui, _lua: Lua, (): ()) -> Result<()> {
+    // run it separately as whatever called this may be cancelled by the reload
+    ui.clone().runtime.spawn_local(async move { ui.reload_lua().await })?.await?
+}
+
 fn get_cwd(ui: &Ui, _lua: &Lua, (): ()) -> Result<BString> {
     Ok(ui.shell.get_cwd())
 }
 
+fn resolve_command(ui: &Ui, lua: &Lua, name: BString) -> Result<LuaValue> {
+    let command = ui.shell.resolve_command(MetaString::from(name).as_ref());
+    Ok(lua.to_value_with(&command, mlua::SerializeOptions::new().serialize_none_to_null(false))?)
+}
+
+fn expand(ui: &Ui, lua: &Lua, (word, options): (BString, Option<LuaValue>)) -> Result<Vec<BString>> {
+    let options = if let Some(options) = options {
+        lua.from_value(options)?
+    } else {
+        Default::default()
+    };
+    ui.shell.expand(word, options)
+}
+
 fn get_size(ui: &Ui, _lua: &Lua, (): ()) -> Result<(u32, u32)> {
     Ok(ui.try_borrow()?.size)
 }
@@ -156,6 +258,10 @@ async fn print(ui: Ui, _lua: Lua, value: BString) -> Result<()> {
     Ok(())
 }
 
+fn show_error_message(ui: &Ui, _lua: &Lua, msg: String) -> Result<()> {
+    ui.show_error_message(&msg)
+}
+
 async fn set_interrupt_key(ui: Ui, _lua: Lua, label: String) -> Result<()> {
     if let EventIndex::Key(key) = EventIndex::parse_from_label(&label)?
         && let Some(byte) = key.try_into_byte()
@@ -171,9 +277,11 @@ fn time(_lua: &Lua, (): ()) -> LuaResult<f64> {
 }
 
 async fn sleep(_lua: Lua, seconds: f64) -> LuaResult<()> {
-    crate::interrupter::run(tokio::time::sleep(std::time::Duration::from_secs_f64(seconds))).await
-        .map_err(|e| mlua::Error::RuntimeError(e.to_string()))?;
-    Ok(())
+    asyncio::run_cancellable(async {
+        crate::interrupter::run(tokio::time::sleep(std::time::Duration::from_secs_f64(seconds))).await
+            .map_err(|e| mlua::Error::RuntimeError(e.to_string()))?;
+        Ok(())
+    }).await
 }
 
 fn shell_quote(_lua: &Lua, val: BString) -> LuaResult<BString> {
@@ -202,6 +310,8 @@ async fn lua_try(lua: Lua, args: LuaTable) -> LuaResult<LuaMultiValue> {
                 let catch_result: LuaResult<LuaValue> = crate::lua::call_lua_fn(&catch, err.clone()).await;
                 error = Some(err);
                 match catch_result {
+                    // like pcall, this must not hide running out of time
+                    Ok(_) if crate::lua::time_limit_expired() => Err(LuaError::RuntimeError("time limit exceeded".into())),
                     Ok(_) => Ok(LuaMultiValue::new()),
                     Err(new_err) if new_err.to_string() == e.to_string() => Err(e),
                     Err(new_err) => Err(new_err.context(e)),
@@ -222,6 +332,26 @@ async fn lua_try(lua: Lua, args: LuaTable) -> LuaResult<LuaMultiValue> {
     result
 }
 
+// like pcall, but errors if func runs for longer than limit seconds without yielding
+// on failure, also returns whether it was because of the time limit
+async fn call_with_time_limit(lua: Lua, (limit, func, args): (f64, LuaFunction, LuaMultiValue)) -> LuaResult<LuaMultiValue> {
+    // 0 (or anything else that is not a valid duration) means no limit
+    let (result, expired) = match std::time::Duration::try_from_secs_f64(limit) {
+        Ok(limit) if !limit.is_zero() => crate::lua::call_lua_fn_with_time_limit(&func, args, limit).await,
+        _ => (crate::lua::call_lua_fn(&func, args).await, false),
+    };
+
+    match result {
+        // the error may have been caught but it still ran out of time
+        Ok(_) if expired => (false, "time limit exceeded", true).into_lua_multi(&lua),
+        Ok(mut values) => {
+            values.push_front(LuaValue::Boolean(true));
+            Ok(values)
+        },
+        Err(err) => (false, err.into_lua(&lua)?, expired).into_lua_multi(&lua),
+    }
+}
+
 pub fn init_lua(lua: &LuaWrapper) -> Result<()> {
 
     lua.set_fn("get_cursor", get_cursor)?;
@@ -232,18 +362,30 @@ pub fn init_lua(lua: &LuaWrapper) -> Result<()> {
     lua.set_async_fn("delete_at_cursor", delete_at_cursor)?;
     lua.set_async_fn("undo_buffer", undo_buffer)?;
     lua.set_async_fn("redo_buffer", redo_buffer)?;
+    lua.set_async_fn("undo_goto", undo_goto)?;
+    lua.set_async_fn("undo_earlier", undo_earlier)?;
+    lua.set_async_fn("undo_later", undo_later)?;
+    lua.set_fn("undo_group_begin", undo_group_begin)?;
+    lua.set_fn("undo_group_end", undo_group_end)?;
+    lua.set_async_fn("undo_join", undo_join)?;
+    lua.set_fn("get_undo_tree", get_undo_tree)?;
     lua.set_async_fn("accept_line", accept_line)?;
     lua.set_async_fn("redraw",  redraw)?;
     lua.set_async_fn("exit", exit)?;
+    lua.set_async_fn("reload", reload)?;
     lua.set_fn("get_cwd", get_cwd)?;
     lua.set_fn("get_size", get_size)?;
+    lua.set_fn("resolve_command", resolve_command)?;
+    lua.set_fn("expand", expand)?;
     lua.set_async_fn("call_hook_func", call_hook_func)?;
     lua.set_async_fn("print", print)?;
     lua.set_async_fn("set_interrupt_key", set_interrupt_key)?;
+    lua.set_fn("show_error_message", show_error_message)?;
     lua.api.set("sleep", lua.create_async_function(sleep)?)?;
     lua.api.set("time", lua.create_function(time)?)?;
     lua.api.set("shell_quote", lua.create_function(shell_quote)?)?;
     lua.api.set("try", lua.create_async_function(lua_try)?)?;
+    lua.api.set("call_with_time_limit", lua.create_async_function(call_with_time_limit)?)?;
     lua.api.set("MAXNUM", lua.create_any_userdata(number::MaxNumber)?)?;
 
     keybind::init_lua(lua)?;
@@ -254,11 +396,16 @@ pub fn init_lua(lua: &LuaWrapper) -> Result<()> {
     tui::init_lua(lua)?;
     log::init_lua(lua)?;
     asyncio::init_lua(lua)?;
+    fs::init_lua(lua)?;
+    net::init_lua(lua)?;
+    remote::init_lua(lua)?;
     process::init_lua(lua)?;
     parser::init_lua(lua)?;
     variables::init_lua(lua)?;
     functions::init_lua(lua)?;
     regex::init_lua(lua)?;
+    abbr::init_lua(lua)?;
+    options::init_lua(lua)?;
 
     Ok(())
 }
@@ -0,0 +1,67 @@
+use bstr::{BString};
+use anyhow::Result;
+use mlua::{prelude::*};
+use crate::lua::{LuaWrapper, auto_from_lua, FromLuaStr};
+use crate::ui::{Ui, abbr::{Abbreviation, Position}};
+
+auto_from_lua! {
+    #[derive(Debug, Default)]
+    struct AbbrOptions {
+        position: Option<FromLuaStr<Position>>,
+        regex: Option<String>,
+        cursor_marker: Option<BString>,
+    }
+}
+
+fn add(ui: &Ui, _lua: &Lua, (word, expansion, opts): (BString, BString, Option<AbbrOptions>)) -> Result<()> {
+    let opts = opts.unwrap_or_default();
+    let regex = opts.regex
+        // anchor it so that it matches the whole word
+        // and not just the leftmost match, e.g. a|ab against ab
+        .map(|r| regex::bytes::Regex::new(&format!("^(?:{r})$")).map(|regex| (r, regex)))
+        .transpose()?;
+
+    ui.try_borrow_mut()?.abbreviations.add(Abbreviation{
+        word,
+        expansion,
+        position: opts.position.map(|p| p.0).unwrap_or_default(),
+        regex,
+        cursor_marker: opts.cursor_marker,
+    });
+    Ok(())
+}
+
+fn remove(ui: &Ui, _lua: &Lua, word: BString) -> Result<bool> {
+    Ok(ui.try_borrow_mut()?.abbreviations.remove(word.as_ref()))
+}
+
+fn list(ui: &Ui, lua: &Lua, (): ()) -> Result<Vec<LuaTable>> {
+    let ui = ui.try_borrow()?;
+    let list = ui.abbreviations.iter().map(|abbr| {
+        let tbl = lua.create_table()?;
+        tbl.raw_set("word", lua.create_string(&abbr.word)?)?;
+        tbl.raw_set("expansion", lua.create_string(&abbr.expansion)?)?;
+        tbl.raw_set("position", abbr.position.to_string())?;
+        tbl.raw_set("regex", abbr.regex.as_ref().map(|(r, _)| r.as_str()))?;
+        tbl.raw_set("cursor_marker", abbr.cursor_marker.as_ref().map(|m| lua.create_string(m)).transpose()?)?;
+        Ok(tbl)
+    }).collect::<LuaResult<_>>()?;
+    Ok(list)
+}
+
+async fn expand(ui: Ui, _lua: Lua, (): ()) -> Result<bool> {
+    Ok(ui.expand_abbreviation().await?.is_some())
+}
+
+pub fn init_lua(lua: &LuaWrapper) -> Result<()> {
+
+    let tbl = lua.create_table()?;
+    lua.api.set("abbr", &tbl)?;
+
+    tbl.set("add", lua.make_fn(add)?)?;
+    tbl.set("remove", lua.make_fn(remove)?)?;
+    tbl.set("list", lua.make_fn(list)?)?;
+    tbl.set("expand", lua.make_async_fn(expand)?)?;
+
+    Ok(())
+}
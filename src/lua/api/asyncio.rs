@@ -4,15 +4,18 @@ use anyhow::Result;
 use mlua::{prelude::*, UserData, UserDataMethods};
 use crate::ui::Ui;
 mod file;
-pub use file::{ReadableFile, WriteableFile};
+mod task;
+pub use file::{ReadableFile, WriteableFile, ReadWriteFile};
+pub use task::run_cancellable;
 
 fn schedule(ui: &Ui, _lua: &Lua, cb: LuaFunction) -> Result<()> {
     let ui = ui.clone();
-    ui.clone().runtime.spawn_local(async move {
+    let config_scope = ui.get_config_scope();
+    ui.clone().runtime.spawn_local(config_scope.run(async move {
         ui.queue_scheduled_callbacks();
         ui.scheduled_callback_notify.notified().await;
         crate::log_if_err(ui.call_lua_fn(false, cb, ()).await);
-    })?;
+    }))?;
     Ok(())
 }
 
@@ -31,13 +34,13 @@ impl UserData for Sender {
 }
 impl UserData for Receiver {
     fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
-        methods.add_async_meta_method(mlua::MetaMethod::Call, |_lua, receiver, ()| async move {
+        methods.add_async_meta_method(mlua::MetaMethod::Call, |_lua, receiver, ()| run_cancellable(async move {
             if let Some(receiver) = receiver.0.take() {
                 Ok(Some(receiver.await.map_err(|e| LuaError::RuntimeError(e.to_string()))?))
             } else {
                 Ok(None)
             }
-        });
+        }));
     }
 }
 
@@ -58,5 +61,10 @@ pub fn init_lua(lua: &LuaWrapper) -> Result<()> {
         ))
     })?)?;
 
+    tbl.set("spawn_task", lua.make_fn(task::spawn_task)?)?;
+    tbl.set("gather", lua.make_async_fn(task::gather)?)?;
+    tbl.set("select", lua.make_async_fn(task::select)?)?;
+    tbl.set("timeout", lua.make_async_fn(task::timeout)?)?;
+
     Ok(())
 }
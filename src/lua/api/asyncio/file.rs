@@ -9,6 +9,7 @@ use tokio::io::{
     AsyncReadExt,
     AsyncWriteExt,
     AsyncBufReadExt,
+    AsyncSeekExt,
 };
 use tokio::sync::{RwLock, RwLockWriteGuard};
 
@@ -37,7 +38,7 @@ impl<T: 'static + AsyncRead> Readable<BufReader<T>> for ReadableFile<T> {
 
 fn add_readable_methods<R: 'static+AsyncRead+Unpin, T: 'static+Readable<R>, M: UserDataMethods<T>>(methods: &mut M) {
 
-    methods.add_async_method("read", |lua, file, ()| async move {
+    methods.add_async_method("read", |lua, file, ()| super::run_cancellable(async move {
         let is_tty_master = file.is_tty_master();
         if let Some(file) = &mut *file.get_reader().await {
             let mut buf = [0; 4096];
@@ -55,22 +56,22 @@ fn add_readable_methods<R: 'static+AsyncRead+Unpin, T: 'static+Readable<R>, M: U
             }
         }
         Ok(None)
-    });
+    }));
 
-    methods.add_async_method("read_to_end", |lua, file, ()| async move {
+    methods.add_async_method("read_to_end", |lua, file, ()| super::run_cancellable(async move {
         if let Some(file) = &mut *file.get_reader().await {
             let mut buf = vec![];
             file.read_to_end(&mut buf).await?;
             return Ok(Some(lua.create_string(&buf)?));
         }
         Ok(None)
-    });
+    }));
 
 }
 
 fn add_bufreadable_methods<R: 'static+AsyncRead+AsyncBufReadExt+Unpin, T: 'static+Readable<R>, M: UserDataMethods<T>>(methods: &mut M) {
 
-    methods.add_async_method("read_until", |lua, file, val: u8| async move {
+    methods.add_async_method("read_until", |lua, file, val: u8| super::run_cancellable(async move {
         if let Some(file) = &mut *file.get_reader().await {
             let mut buf = vec![];
             let n = file.read_until(val, &mut buf).await?;
@@ -79,10 +80,10 @@ fn add_bufreadable_methods<R: 'static+AsyncRead+AsyncBufReadExt+Unpin, T: 'stati
             }
         }
         Ok(None)
-    });
+    }));
 
 
-    methods.add_async_method("read_line", |lua, file, ()| async move {
+    methods.add_async_method("read_line", |lua, file, ()| super::run_cancellable(async move {
         if let Some(file) = &mut *file.get_reader().await {
             let mut buf = vec![];
             let n = file.read_until(b'\n', &mut buf).await?;
@@ -91,18 +92,18 @@ fn add_bufreadable_methods<R: 'static+AsyncRead+AsyncBufReadExt+Unpin, T: 'stati
             }
         }
         Ok(None)
-    });
+    }));
 
 }
 
 fn add_writeable_methods<R: 'static+AsyncWrite+Unpin, T: 'static+Writeable<R>, M: UserDataMethods<T>>(methods: &mut M) {
-    methods.add_async_method("write", |_lua, file, val: LuaString| async move {
+    methods.add_async_method("write", |_lua, file, val: LuaString| super::run_cancellable(async move {
         if let Some(file) = &mut *file.get_writer().await {
             file.write_all(&val.as_bytes()).await?;
             file.flush().await?;
         }
         Ok(())
-    });
+    }));
 }
 
 impl<T: AsyncRead + AsRawFd> ReadableFile<T> {
@@ -172,10 +173,54 @@ impl<T: AsyncWrite + AsRawFd + Unpin + 'static> UserData for WriteableFile<T> {
         });
 
         methods.add_async_method("close", |_lua, file, ()| async move {
-            *file.inner.write().await = None;
+            // shutdown so that sockets see eof
+            if let Some(mut writer) = file.inner.write().await.take() {
+                writer.shutdown().await?;
+            }
             Ok(())
         });
 
         add_writeable_methods::<BufWriter<T>, Self, M>(methods);
     }
 }
+
+// read and written through the one handle so that both share the same file offset
+pub struct ReadWriteFile {
+    pub inner: RwLock<Option<BufReader<tokio::fs::File>>>,
+    pub fd: RawFd,
+}
+impl Readable<BufReader<tokio::fs::File>> for ReadWriteFile {
+    async fn get_reader(&self) -> RwLockWriteGuard<'_, Option<BufReader<tokio::fs::File>>> {
+        self.inner.write().await
+    }
+    fn is_tty_master(&self) -> bool {
+        false
+    }
+}
+
+impl UserData for ReadWriteFile {
+    fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("as_fd", |_lua, file, ()| {
+            Ok(file.fd)
+        });
+
+        methods.add_async_method("close", |_lua, file, ()| async move {
+            *file.inner.write().await = None;
+            Ok(())
+        });
+
+        methods.add_async_method("write", |_lua, file, val: LuaString| super::run_cancellable(async move {
+            if let Some(file) = &mut *file.inner.write().await {
+                // throw away anything read ahead so the write lands where reading got up to
+                file.seek(std::io::SeekFrom::Current(0)).await?;
+                let file = file.get_mut();
+                file.write_all(&val.as_bytes()).await?;
+                file.flush().await?;
+            }
+            Ok(())
+        }));
+
+        add_readable_methods(methods);
+        add_bufreadable_methods(methods);
+    }
+}
@@ -0,0 +1,190 @@
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::Duration;
+use anyhow::Result;
+use mlua::{prelude::*, UserData, UserDataMethods};
+use tokio::sync::watch;
+use crate::canceller::{self, Canceller, SharedCancellable};
+use crate::ui::Ui;
+
+struct TaskScope {
+    cancellable: SharedCancellable,
+    // tasks are also cancelled when the config that started them is reloaded
+    config_scope: SharedCancellable,
+    delivered: Cell<bool>,
+}
+
+impl TaskScope {
+    fn is_cancelled(&self) -> bool {
+        self.cancellable.is_cancelled() || self.config_scope.is_cancelled()
+    }
+
+    async fn cancelled(&self) {
+        tokio::select!(
+            () = self.cancellable.cancelled() => (),
+            () = self.config_scope.cancelled() => (),
+        );
+        // only deliver the cancellation once
+        // so that cleanup code in the task can still await things
+        if self.delivered.replace(true) {
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+tokio::task_local! {
+    static CURRENT_TASK: Rc<TaskScope>;
+}
+
+fn cancelled_error() -> LuaError {
+    LuaError::RuntimeError("cancelled".into())
+}
+
+// raises a cancelled error if the task we are running in gets cancelled while awaiting f
+pub async fn run_cancellable<T, F: Future<Output=LuaResult<T>>>(f: F) -> LuaResult<T> {
+    let Ok(scope) = CURRENT_TASK.try_with(|scope| scope.clone())
+        else { return f.await };
+    if scope.delivered.get() {
+        return f.await
+    }
+
+    tokio::select!(
+        biased;
+        result = f => result,
+        () = scope.cancelled() => Err(cancelled_error()),
+    )
+}
+
+#[derive(Clone)]
+pub struct Task {
+    result: watch::Receiver<Option<LuaResult<LuaMultiValue>>>,
+    canceller: Rc<Cell<Option<Canceller>>>,
+}
+
+impl Task {
+    fn spawn(ui: &Ui, func: LuaFunction, args: LuaMultiValue) -> Result<Self> {
+        let (canceller, cancellable) = canceller::new();
+        let canceller = Rc::new(Cell::new(Some(canceller)));
+        let (sender, receiver) = watch::channel(None);
+        let scope = Rc::new(TaskScope{
+            cancellable: cancellable.shared(),
+            config_scope: ui.get_config_scope(),
+            delivered: Cell::new(false),
+        });
+
+        // the task holds on to the canceller as well
+        // otherwise it would be cancelled as soon as lua drops the handle
+        let keepalive = canceller.clone();
+        ui.runtime.spawn_local(CURRENT_TASK.scope(scope.clone(), async move {
+            let result = if scope.is_cancelled() {
+                Err(cancelled_error())
+            } else {
+                crate::lua::call_lua_fn(&func, args).await
+            };
+            let _ = sender.send(Some(result));
+            drop(keepalive);
+        }))?;
+
+        Ok(Self{ result: receiver, canceller })
+    }
+
+    fn is_done(&self) -> bool {
+        self.result.borrow().is_some()
+    }
+
+    fn cancel(&self) -> bool {
+        match self.canceller.take() {
+            Some(canceller) if !self.is_done() => {
+                canceller.cancel();
+                true
+            },
+            _ => false,
+        }
+    }
+
+    async fn wait(&self) -> LuaResult<LuaMultiValue> {
+        let mut result = self.result.clone();
+        match result.wait_for(|x| x.is_some()).await {
+            Ok(x) => x.as_ref().unwrap().clone(),
+            // the runtime has gone away
+            Err(_) => Err(cancelled_error()),
+        }
+    }
+}
+
+impl UserData for Task {
+    fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
+        methods.add_async_method("wait", |_lua, task, ()| run_cancellable(async move {
+            task.wait().await
+        }));
+
+        methods.add_method("cancel", |_lua, task, ()| {
+            Ok(task.cancel())
+        });
+
+        methods.add_method("is_done", |_lua, task, ()| {
+            Ok(task.is_done())
+        });
+    }
+}
+
+// cancels any unfinished tasks when dropped
+struct TaskGroup(Vec<Task>);
+
+impl TaskGroup {
+    fn from_lua_args(ui: &Ui, args: LuaMultiValue) -> Result<Self> {
+        let mut group = Self(Vec::with_capacity(args.len()));
+        for arg in args {
+            let task = match arg {
+                LuaValue::Function(func) => Task::spawn(ui, func, LuaMultiValue::new())?,
+                LuaValue::UserData(ud) => Task::clone(&*ud.borrow::<Task>()?),
+                arg => anyhow::bail!("expected a task or function, got a {}", arg.type_name()),
+            };
+            group.0.push(task);
+        }
+        Ok(group)
+    }
+}
+
+impl Drop for TaskGroup {
+    fn drop(&mut self) {
+        for task in &self.0 {
+            task.cancel();
+        }
+    }
+}
+
+pub fn spawn_task(ui: &Ui, _lua: &Lua, (func, args): (LuaFunction, LuaMultiValue)) -> Result<Task> {
+    Task::spawn(ui, func, args)
+}
+
+pub async fn gather(ui: Ui, _lua: Lua, args: LuaMultiValue) -> Result<LuaMultiValue> {
+    let group = TaskGroup::from_lua_args(&ui, args)?;
+    // the first error drops the group, which cancels everything else
+    let results = futures::future::try_join_all(group.0.iter().map(|task| task.wait())).await?;
+    Ok(results.into_iter().map(|mut values| values.pop_front().unwrap_or(LuaNil)).collect())
+}
+
+pub async fn select(ui: Ui, lua: Lua, args: LuaMultiValue) -> Result<LuaMultiValue> {
+    let group = TaskGroup::from_lua_args(&ui, args)?;
+    if group.0.is_empty() {
+        anyhow::bail!("no tasks given");
+    }
+
+    // everything except the winner gets cancelled when the group is dropped
+    let (result, index, _) = futures::future::select_all(group.0.iter().map(|task| Box::pin(task.wait()))).await;
+    let mut values = result?;
+    values.push_front((index + 1).into_lua(&lua)?);
+    Ok(values)
+}
+
+pub async fn timeout(ui: Ui, _lua: Lua, (seconds, func, args): (f64, LuaFunction, LuaMultiValue)) -> Result<LuaMultiValue> {
+    let Ok(duration) = Duration::try_from_secs_f64(seconds) else {
+        anyhow::bail!("invalid timeout: {seconds}");
+    };
+    let group = TaskGroup(vec![Task::spawn(&ui, func, args)?]);
+    match tokio::time::timeout(duration, group.0[0].wait()).await {
+        Ok(result) => Ok(result?),
+        Err(_) => anyhow::bail!("timed out after {seconds}s"),
+    }
+}
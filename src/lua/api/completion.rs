@@ -4,6 +4,7 @@ use crate::lua::{Ui};
 use anyhow::Result;
 use mlua::{prelude::*, UserData, UserDataMethods, MetaMethod};
 use std::rc::Rc;
+use bstr::ByteSlice;
 
 #[derive(FromLua, Clone)]
 struct Match {
@@ -37,7 +38,7 @@ async fn get_completions(ui: Ui, _lua: Lua, (val, callback): (Option<String>, Lu
     ui.shell.trampoline_out_callback(move |ui, token| {
         let ui = ui.clone();
         let ui2 = ui.clone();
-        let result = ui2.shell.get_completions(token, val, Box::new(move |matches| {
+        let (result, errors) = ui2.shell.get_completions(token, val, Box::new(move |matches| {
 
             let result = (|| {
                 let matches = ui.lua.create_sequence_from(matches.into_iter().map(|x| Match{inner: Rc::new(x)}))?;
@@ -52,6 +53,10 @@ async fn get_completions(ui: Ui, _lua: Lua, (val, callback): (Option<String>, Lu
             }
         }));
 
+        if let Some(errors) = errors && !errors.is_empty() {
+            ::log::warn!("{}", errors.trim_end().as_bstr());
+        }
+
         if let Some(msg) = result && !msg.is_empty() {
             let tui = &mut ui2.try_borrow_mut()?.tui;
             tui.clear_zle();
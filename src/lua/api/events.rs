@@ -82,6 +82,16 @@ macro_rules! event_types {
                 }
             }
 
+            pub fn has_callbacks(&self, typ: EventType) -> bool {
+                !self.get_callbacks(typ).inner.borrow().is_empty()
+            }
+
+            pub fn clear(&self) {
+            $(
+                self.$name.modify(|vec| vec.clear());
+            )*
+            }
+
             fn remove_event_callback(&self, id: usize) {
             $(
                 if self.$name.remove(id) {
@@ -165,11 +175,13 @@ event_types!(
     accept_line(data: &BStr),
     buffer_change(),
     buffer_cursor_move(),
+    parse_change(),
     precmd(data: Option<&BStr>),
     paste(data: &BStr),
     window_resize(width: u32, height: u32),
     message_resize(ids: &[usize]),
     exit(val: i32),
+    option_changed(name: &str),
 );
 
 
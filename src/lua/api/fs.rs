@@ -0,0 +1,290 @@
+use std::ffi::OsStr;
+use std::fs::{Metadata, FileType};
+use std::os::fd::AsRawFd;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{MetadataExt, FileTypeExt, DirBuilderExt};
+use std::path::Path;
+use bstr::BString;
+use serde::Serialize;
+use anyhow::Result;
+use mlua::prelude::*;
+use tokio::io::{BufReader, BufWriter};
+use tokio::sync::RwLock;
+use crate::lua::{LuaWrapper, auto_from_lua, lua_error};
+use super::asyncio::{run_cancellable, ReadableFile, WriteableFile, ReadWriteFile};
+mod watch;
+
+pub(super) fn to_path(path: &BString) -> &Path {
+    Path::new(OsStr::from_bytes(path))
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Serialize)]
+enum FileKind {
+    file,
+    dir,
+    symlink,
+    fifo,
+    socket,
+    block_device,
+    char_device,
+    unknown,
+}
+
+impl From<FileType> for FileKind {
+    fn from(typ: FileType) -> Self {
+        if typ.is_file() {
+            Self::file
+        } else if typ.is_dir() {
+            Self::dir
+        } else if typ.is_symlink() {
+            Self::symlink
+        } else if typ.is_fifo() {
+            Self::fifo
+        } else if typ.is_socket() {
+            Self::socket
+        } else if typ.is_block_device() {
+            Self::block_device
+        } else if typ.is_char_device() {
+            Self::char_device
+        } else {
+            Self::unknown
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Stat {
+    r#type: FileKind,
+    size: u64,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    nlink: u64,
+    inode: u64,
+    dev: u64,
+    atime: f64,
+    mtime: f64,
+    ctime: f64,
+}
+
+impl From<Metadata> for Stat {
+    fn from(metadata: Metadata) -> Self {
+        Self {
+            r#type: metadata.file_type().into(),
+            size: metadata.size(),
+            mode: metadata.mode(),
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            nlink: metadata.nlink(),
+            inode: metadata.ino(),
+            dev: metadata.dev(),
+            atime: metadata.atime() as f64 + metadata.atime_nsec() as f64 / 1e9,
+            mtime: metadata.mtime() as f64 + metadata.mtime_nsec() as f64 / 1e9,
+            ctime: metadata.ctime() as f64 + metadata.ctime_nsec() as f64 / 1e9,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct DirEntry {
+    name: BString,
+    r#type: FileKind,
+}
+
+auto_from_lua! {
+    #[derive(Debug, Default)]
+    struct MkdirOptions {
+        parents: Option<bool>,
+        mode: Option<u32>,
+    }
+}
+
+auto_from_lua! {
+    #[derive(Debug, Default)]
+    struct RemoveOptions {
+        recursive: Option<bool>,
+    }
+}
+
+fn open(lua: &Lua, (path, mode): (BString, Option<String>)) -> LuaResult<LuaMultiValue> {
+    let mut options = std::fs::OpenOptions::new();
+    let mode = mode.as_deref().unwrap_or("r").replace('b', "");
+    let (read, write) = match mode.as_str() {
+        "r"  => { options.read(true); (true, false) },
+        "r+" => { options.read(true).write(true); (true, true) },
+        "w"  => { options.write(true).create(true).truncate(true); (false, true) },
+        "w+" => { options.read(true).write(true).create(true).truncate(true); (true, true) },
+        "a"  => { options.append(true).create(true); (false, true) },
+        "a+" => { options.read(true).append(true).create(true); (true, true) },
+        _ => return Err(lua_error(format!("invalid mode: {mode:?}"))),
+    };
+    let file = tokio::fs::File::from_std(options.open(to_path(&path))?);
+    let fd = file.as_raw_fd();
+
+    match (read, write) {
+        (true, true) => {
+            // the same object is returned as both the reader and the writer
+            let file = lua.create_userdata(ReadWriteFile{
+                fd,
+                inner: RwLock::new(Some(BufReader::new(file))),
+            })?;
+            lua.pack_multi((file.clone(), file))
+        },
+        (true, false) => lua.pack_multi(ReadableFile{
+            fd,
+            inner: RwLock::new(Some(BufReader::new(file))),
+            is_tty_master: false,
+        }),
+        (false, _) => lua.pack_multi(WriteableFile{
+            fd,
+            inner: RwLock::new(Some(BufWriter::new(file))),
+        }),
+    }
+}
+
+fn stat(lua: &Lua, path: BString) -> LuaResult<LuaValue> {
+    lua.to_value(&Stat::from(std::fs::metadata(to_path(&path))?))
+}
+
+fn lstat(lua: &Lua, path: BString) -> LuaResult<LuaValue> {
+    lua.to_value(&Stat::from(std::fs::symlink_metadata(to_path(&path))?))
+}
+
+fn readdir(lua: &Lua, path: BString) -> LuaResult<LuaValue> {
+    let mut entries = vec![];
+    for entry in std::fs::read_dir(to_path(&path))? {
+        let entry = entry?;
+        entries.push(DirEntry{
+            name: entry.file_name().as_bytes().into(),
+            r#type: entry.file_type()?.into(),
+        });
+    }
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    lua.to_value(&entries)
+}
+
+fn exists(_lua: &Lua, path: BString) -> LuaResult<bool> {
+    Ok(std::fs::exists(to_path(&path))?)
+}
+
+fn mkdir(_lua: &Lua, (path, options): (BString, Option<MkdirOptions>)) -> LuaResult<()> {
+    let options = options.unwrap_or_default();
+    let mut builder = std::fs::DirBuilder::new();
+    builder.recursive(options.parents.unwrap_or(false));
+    if let Some(mode) = options.mode {
+        builder.mode(mode);
+    }
+    Ok(builder.create(to_path(&path))?)
+}
+
+fn rename(_lua: &Lua, (from, to): (BString, BString)) -> LuaResult<()> {
+    Ok(std::fs::rename(to_path(&from), to_path(&to))?)
+}
+
+fn remove(_lua: &Lua, (path, options): (BString, Option<RemoveOptions>)) -> LuaResult<()> {
+    let path = to_path(&path);
+    if !std::fs::symlink_metadata(path)?.is_dir() {
+        std::fs::remove_file(path)?;
+    } else if options.and_then(|o| o.recursive).unwrap_or(false) {
+        std::fs::remove_dir_all(path)?;
+    } else {
+        std::fs::remove_dir(path)?;
+    }
+    Ok(())
+}
+
+async fn async_stat(lua: Lua, path: BString) -> LuaResult<LuaValue> {
+    run_cancellable(async move {
+        lua.to_value(&Stat::from(tokio::fs::metadata(to_path(&path)).await?))
+    }).await
+}
+
+async fn async_lstat(lua: Lua, path: BString) -> LuaResult<LuaValue> {
+    run_cancellable(async move {
+        lua.to_value(&Stat::from(tokio::fs::symlink_metadata(to_path(&path)).await?))
+    }).await
+}
+
+async fn async_readdir(lua: Lua, path: BString) -> LuaResult<LuaValue> {
+    run_cancellable(async move {
+        let mut entries = vec![];
+        let mut dir = tokio::fs::read_dir(to_path(&path)).await?;
+        while let Some(entry) = dir.next_entry().await? {
+            entries.push(DirEntry{
+                name: entry.file_name().as_bytes().into(),
+                r#type: entry.file_type().await?.into(),
+            });
+        }
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        lua.to_value(&entries)
+    }).await
+}
+
+async fn async_exists(_lua: Lua, path: BString) -> LuaResult<bool> {
+    run_cancellable(async move {
+        Ok(tokio::fs::try_exists(to_path(&path)).await?)
+    }).await
+}
+
+async fn async_mkdir(_lua: Lua, (path, options): (BString, Option<MkdirOptions>)) -> LuaResult<()> {
+    run_cancellable(async move {
+        let options = options.unwrap_or_default();
+        let mut builder = tokio::fs::DirBuilder::new();
+        builder.recursive(options.parents.unwrap_or(false));
+        if let Some(mode) = options.mode {
+            builder.mode(mode);
+        }
+        Ok(builder.create(to_path(&path)).await?)
+    }).await
+}
+
+async fn async_rename(_lua: Lua, (from, to): (BString, BString)) -> LuaResult<()> {
+    run_cancellable(async move {
+        Ok(tokio::fs::rename(to_path(&from), to_path(&to)).await?)
+    }).await
+}
+
+async fn async_remove(_lua: Lua, (path, options): (BString, Option<RemoveOptions>)) -> LuaResult<()> {
+    run_cancellable(async move {
+        let path = to_path(&path);
+        if !tokio::fs::symlink_metadata(path).await?.is_dir() {
+            tokio::fs::remove_file(path).await?;
+        } else if options.and_then(|o| o.recursive).unwrap_or(false) {
+            tokio::fs::remove_dir_all(path).await?;
+        } else {
+            tokio::fs::remove_dir(path).await?;
+        }
+        Ok(())
+    }).await
+}
+
+pub fn init_lua(lua: &LuaWrapper) -> Result<()> {
+
+    let tbl = lua.create_table()?;
+    lua.api.set("fs", &tbl)?;
+
+    tbl.set("open", lua.create_function(open)?)?;
+    tbl.set("stat", lua.create_function(stat)?)?;
+    tbl.set("lstat", lua.create_function(lstat)?)?;
+    tbl.set("readdir", lua.create_function(readdir)?)?;
+    tbl.set("exists", lua.create_function(exists)?)?;
+    tbl.set("mkdir", lua.create_function(mkdir)?)?;
+    tbl.set("rename", lua.create_function(rename)?)?;
+    tbl.set("remove", lua.create_function(remove)?)?;
+    tbl.set("watch", lua.make_fn(watch::watch)?)?;
+
+    // async variants live under wish.async.fs
+    let async_tbl = lua.create_table()?;
+    lua.api.get::<LuaTable>("async")?.set("fs", &async_tbl)?;
+
+    async_tbl.set("stat", lua.create_async_function(async_stat)?)?;
+    async_tbl.set("lstat", lua.create_async_function(async_lstat)?)?;
+    async_tbl.set("readdir", lua.create_async_function(async_readdir)?)?;
+    async_tbl.set("exists", lua.create_async_function(async_exists)?)?;
+    async_tbl.set("mkdir", lua.create_async_function(async_mkdir)?)?;
+    async_tbl.set("rename", lua.create_async_function(async_rename)?)?;
+    async_tbl.set("remove", lua.create_async_function(async_remove)?)?;
+
+    Ok(())
+}
@@ -0,0 +1,234 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::os::fd::{AsFd, AsRawFd, FromRawFd, IntoRawFd, OwnedFd, RawFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use bstr::BString;
+use serde::Serialize;
+use anyhow::Result;
+use mlua::{prelude::*, UserData, UserDataMethods};
+use nix::sys::inotify::{Inotify, InitFlags, AddWatchFlags, WatchDescriptor, InotifyEvent};
+use tokio::io::unix::AsyncFd;
+use crate::canceller::{self, Canceller};
+use crate::lua::{auto_from_lua, Array};
+use crate::ui::Ui;
+
+const EVENT_NAMES: &[(&str, AddWatchFlags)] = &[
+    ("access",        AddWatchFlags::IN_ACCESS),
+    ("attrib",        AddWatchFlags::IN_ATTRIB),
+    ("close_write",   AddWatchFlags::IN_CLOSE_WRITE),
+    ("close_nowrite", AddWatchFlags::IN_CLOSE_NOWRITE),
+    ("create",        AddWatchFlags::IN_CREATE),
+    ("delete",        AddWatchFlags::IN_DELETE),
+    ("delete_self",   AddWatchFlags::IN_DELETE_SELF),
+    ("modify",        AddWatchFlags::IN_MODIFY),
+    ("move_self",     AddWatchFlags::IN_MOVE_SELF),
+    ("moved_from",    AddWatchFlags::IN_MOVED_FROM),
+    ("moved_to",      AddWatchFlags::IN_MOVED_TO),
+    ("open",          AddWatchFlags::IN_OPEN),
+    ("overflow",      AddWatchFlags::IN_Q_OVERFLOW),
+];
+
+const DEFAULT_EVENTS: AddWatchFlags = AddWatchFlags::IN_ATTRIB
+    .union(AddWatchFlags::IN_CREATE)
+    .union(AddWatchFlags::IN_DELETE)
+    .union(AddWatchFlags::IN_DELETE_SELF)
+    .union(AddWatchFlags::IN_MODIFY)
+    .union(AddWatchFlags::IN_MOVE_SELF)
+    .union(AddWatchFlags::IN_MOVED_FROM)
+    .union(AddWatchFlags::IN_MOVED_TO)
+    .union(AddWatchFlags::IN_Q_OVERFLOW);
+
+auto_from_lua! {
+    #[derive(Debug, Default)]
+    pub struct WatchOptions {
+        recursive: Option<bool>,
+        events: Option<Array<String>>,
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct WatchEvent {
+    path: BString,
+    events: Vec<&'static str>,
+    is_dir: bool,
+    cookie: u32,
+}
+
+// nix does not implement AsRawFd for Inotify, which AsyncFd needs
+struct InotifyFd(Inotify);
+
+impl AsRawFd for InotifyFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_fd().as_raw_fd()
+    }
+}
+
+struct Watcher {
+    inotify: AsyncFd<InotifyFd>,
+    // what we ask inotify for
+    mask: AddWatchFlags,
+    // what we tell lua about
+    report: AddWatchFlags,
+    recursive: bool,
+    paths: HashMap<WatchDescriptor, PathBuf>,
+}
+
+impl Watcher {
+    fn add(&mut self, path: &Path) -> nix::Result<()> {
+        let wd = self.inotify.get_ref().0.add_watch(path, self.mask)?;
+        self.paths.insert(wd, path.to_owned());
+
+        if self.recursive && let Ok(entries) = std::fs::read_dir(path) {
+            for entry in entries.flatten() {
+                if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                    // subdirectories may disappear from under us, just log it
+                    crate::log_if_err(self.add(&entry.path()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn process_events(&mut self, events: Vec<InotifyEvent>) -> Vec<WatchEvent> {
+        let mut result = vec![];
+        for event in events {
+            // this has no watch descriptor, events were lost for everything being watched
+            if event.mask.contains(AddWatchFlags::IN_Q_OVERFLOW) {
+                if self.report.contains(AddWatchFlags::IN_Q_OVERFLOW) {
+                    for path in self.paths.values() {
+                        result.push(WatchEvent{
+                            path: path.as_os_str().as_bytes().into(),
+                            events: vec!["overflow"],
+                            is_dir: path.is_dir(),
+                            cookie: 0,
+                        });
+                    }
+                }
+                continue
+            }
+
+            let dir = if event.mask.contains(AddWatchFlags::IN_IGNORED) {
+                // the watch is gone
+                self.paths.remove(&event.wd)
+            } else {
+                self.paths.get(&event.wd).cloned()
+            };
+            let Some(dir) = dir else { continue };
+
+            let path = match &event.name {
+                Some(name) => dir.join(name),
+                None => dir,
+            };
+            let is_dir = event.mask.contains(AddWatchFlags::IN_ISDIR);
+
+            if self.recursive && is_dir && event.mask.intersects(AddWatchFlags::IN_CREATE | AddWatchFlags::IN_MOVED_TO) {
+                crate::log_if_err(self.add(&path));
+            }
+
+            let mask = event.mask & self.report;
+            if !mask.is_empty() {
+                result.push(WatchEvent{
+                    path: path.as_os_str().as_bytes().into(),
+                    events: EVENT_NAMES.iter().filter(|(_, flag)| mask.contains(*flag)).map(|(name, _)| *name).collect(),
+                    is_dir,
+                    cookie: event.cookie,
+                });
+            }
+        }
+        result
+    }
+
+    async fn run(mut self, ui: Ui, callback: LuaFunction) -> Result<()> {
+        while !self.paths.is_empty() {
+            let mut guard = self.inotify.readable().await?;
+            let events = match guard.try_io(|inotify| inotify.get_ref().0.read_events().map_err(std::io::Error::from)) {
+                Ok(events) => events?,
+                Err(_would_block) => continue,
+            };
+            drop(guard);
+
+            let events = self.process_events(events);
+            if events.is_empty() {
+                continue
+            }
+
+            // deliver them the same way as wish.schedule
+            ui.queue_scheduled_callbacks();
+            ui.scheduled_callback_notify.notified().await;
+            for event in events {
+                let event = ui.lua.to_value(&event)?;
+                crate::log_if_err(ui.call_lua_fn(false, callback.clone(), event).await);
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct FsWatch {
+    canceller: Rc<Cell<Option<Canceller>>>,
+}
+
+impl UserData for FsWatch {
+    fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("cancel", |_lua, watch, ()| {
+            if let Some(canceller) = watch.canceller.take() {
+                canceller.cancel();
+            }
+            Ok(())
+        });
+    }
+}
+
+pub fn watch(ui: &Ui, _lua: &Lua, (path, options, callback): (BString, Option<WatchOptions>, LuaFunction)) -> Result<FsWatch> {
+    let options = options.unwrap_or_default();
+    let recursive = options.recursive.unwrap_or(false);
+
+    let report = if let Some(events) = options.events {
+        let mut report = AddWatchFlags::empty();
+        for event in events.0 {
+            let Some((_, flag)) = EVENT_NAMES.iter().find(|(name, _)| *name == event)
+                else { anyhow::bail!("unknown event: {event:?}") };
+            report |= *flag;
+        }
+        report
+    } else {
+        DEFAULT_EVENTS
+    };
+    let mut mask = report;
+    if recursive {
+        // need these to pick up new subdirectories
+        mask |= AddWatchFlags::IN_CREATE | AddWatchFlags::IN_MOVED_TO;
+    }
+
+    let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)?;
+    // move to an fd >= 10
+    let fd = crate::utils::move_fd(OwnedFd::from(inotify))?;
+    let inotify = InotifyFd(unsafe{ Inotify::from_raw_fd(fd.into_raw_fd()) });
+
+    let mut watcher = Watcher {
+        inotify: ui.runtime.enter(|| AsyncFd::new(inotify))??,
+        mask,
+        report,
+        recursive,
+        paths: HashMap::new(),
+    };
+    watcher.add(super::to_path(&path))?;
+
+    let (canceller, mut cancellable) = canceller::new();
+    let canceller = Rc::new(Cell::new(Some(canceller)));
+    // the watch keeps running until it is explicitly cancelled
+    // or the config is reloaded
+    let keepalive = canceller.clone();
+    let config_scope = ui.get_config_scope();
+    let ui = ui.clone();
+    ui.clone().runtime.spawn_local(async move {
+        if let Some(Some(result)) = config_scope.run(cancellable.run(watcher.run(ui, callback))).await {
+            crate::log_if_err(result);
+        }
+        drop(keepalive);
+    })?;
+
+    Ok(FsWatch{ canceller })
+}
@@ -0,0 +1,171 @@
+use std::cell::Cell;
+use std::os::fd::AsRawFd;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use anyhow::Result;
+use bstr::BString;
+use mlua::{prelude::*, UserData, UserDataMethods};
+use tokio::io::{AsyncRead, AsyncWrite, BufReader, BufWriter};
+use tokio::net::{UnixListener, UnixStream, TcpStream};
+use tokio::sync::RwLock;
+use crate::canceller::{self, Canceller};
+use crate::lua::LuaWrapper;
+use crate::ui::Ui;
+use super::asyncio::{run_cancellable, ReadableFile, WriteableFile};
+
+// removes the socket file once the listener goes away
+pub(super) struct SocketPath(pub PathBuf);
+
+impl Drop for SocketPath {
+    fn drop(&mut self) {
+        // forked children share the socket path with the parent, leave it alone
+        if !crate::is_forked() {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+}
+
+pub(super) fn bind_unix(ui: &Ui, path: &Path) -> Result<(UnixListener, SocketPath)> {
+    // clear out a stale socket left behind by something else
+    // but not one that something is still listening on
+    if std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+        match std::os::unix::net::UnixStream::connect(path) {
+            Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => std::fs::remove_file(path)?,
+            _ => return Err(anyhow::Error::from(std::io::Error::from(std::io::ErrorKind::AddrInUse)).context(format!("{path:?}"))),
+        }
+    }
+
+    let listener = std::os::unix::net::UnixListener::bind(path)?;
+    let path = SocketPath(path.to_owned());
+    // move to an fd >= 10
+    let listener = crate::utils::move_fd(listener)?;
+    listener.set_nonblocking(true)?;
+    let listener = ui.runtime.enter(|| UnixListener::from_std(listener))??;
+    Ok((listener, path))
+}
+
+fn make_files<T: AsyncRead + AsyncWrite + AsRawFd>(reader: T, writer: T) -> (ReadableFile<T>, WriteableFile<T>) {
+    let reader = ReadableFile{
+        fd: reader.as_raw_fd(),
+        inner: RwLock::new(Some(BufReader::new(reader))),
+        is_tty_master: false,
+    };
+    let writer = WriteableFile{
+        fd: writer.as_raw_fd(),
+        inner: RwLock::new(Some(BufWriter::new(writer))),
+    };
+    (reader, writer)
+}
+
+// each half gets its own fd so they can be used and closed independently
+fn split_unix(stream: UnixStream) -> std::io::Result<(ReadableFile<UnixStream>, WriteableFile<UnixStream>)> {
+    let stream = crate::utils::move_fd(stream.into_std()?)?;
+    let writer = crate::utils::move_fd(stream.try_clone()?)?;
+    Ok(make_files(UnixStream::from_std(stream)?, UnixStream::from_std(writer)?))
+}
+
+fn split_tcp(stream: TcpStream) -> std::io::Result<(ReadableFile<TcpStream>, WriteableFile<TcpStream>)> {
+    let stream = crate::utils::move_fd(stream.into_std()?)?;
+    let writer = crate::utils::move_fd(stream.try_clone()?)?;
+    Ok(make_files(TcpStream::from_std(stream)?, TcpStream::from_std(writer)?))
+}
+
+async fn connect_unix(lua: Lua, path: BString) -> LuaResult<LuaMultiValue> {
+    run_cancellable(async move {
+        let stream = UnixStream::connect(super::fs::to_path(&path)).await?;
+        lua.pack_multi(split_unix(stream)?)
+    }).await
+}
+
+async fn connect_tcp(lua: Lua, (host, port): (String, u16)) -> LuaResult<LuaMultiValue> {
+    run_cancellable(async move {
+        let stream = TcpStream::connect((host.as_str(), port)).await?;
+        stream.set_nodelay(true)?;
+        lua.pack_multi(split_tcp(stream)?)
+    }).await
+}
+
+async fn run_listener(ui: Ui, listener: UnixListener, _path: SocketPath, on_conn: LuaFunction) -> Result<()> {
+    // _path is dropped, and the socket removed, when this is cancelled
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let Some((reader, writer)) = crate::log_if_err(split_unix(stream))
+            else { continue };
+
+        // each connection gets handled concurrently
+        let ui = ui.clone();
+        let on_conn = on_conn.clone();
+        let config_scope = ui.get_config_scope();
+        ui.clone().runtime.spawn_local(config_scope.run(async move {
+            ui.queue_scheduled_callbacks();
+            ui.scheduled_callback_notify.notified().await;
+            crate::log_if_err(ui.call_lua_fn(false, on_conn, (reader, writer)).await);
+        }))?;
+    }
+}
+
+pub struct Listener {
+    path: BString,
+    canceller: Rc<Cell<Option<Canceller>>>,
+}
+
+impl UserData for Listener {
+    fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("path", |lua, listener, ()| {
+            lua.create_string(&listener.path)
+        });
+
+        methods.add_method("close", |_lua, listener, ()| {
+            if let Some(canceller) = listener.canceller.take() {
+                canceller.cancel();
+            }
+            Ok(())
+        });
+    }
+}
+
+impl Listener {
+    pub(super) fn spawn<F, T>(ui: &Ui, path: SocketPath, f: F) -> Result<Self>
+    where
+        F: FnOnce(SocketPath) -> T,
+        T: Future<Output=Result<()>> + 'static,
+    {
+        let path_bytes = path.0.as_os_str().as_bytes().into();
+
+        let (canceller, mut cancellable) = canceller::new();
+        let canceller = Rc::new(Cell::new(Some(canceller)));
+        // the listener keeps running until it is explicitly closed
+        // or the config is reloaded
+        let keepalive = canceller.clone();
+        let config_scope = ui.get_config_scope();
+        let future = f(path);
+        ui.runtime.spawn_local(async move {
+            if let Some(Some(result)) = config_scope.run(cancellable.run(future)).await {
+                crate::log_if_err(result);
+            }
+            drop(keepalive);
+        })?;
+
+        Ok(Self{ path: path_bytes, canceller })
+    }
+}
+
+fn listen_unix(ui: &Ui, _lua: &Lua, (path, on_conn): (BString, LuaFunction)) -> Result<Listener> {
+    let (listener, path) = bind_unix(ui, super::fs::to_path(&path))?;
+    let ui_clone = ui.clone();
+    Listener::spawn(ui, path, move |path| run_listener(ui_clone, listener, path, on_conn))
+}
+
+pub fn init_lua(lua: &LuaWrapper) -> Result<()> {
+
+    let tbl = lua.create_table()?;
+    lua.api.set("net", &tbl)?;
+
+    tbl.set("connect_unix", lua.create_async_function(connect_unix)?)?;
+    tbl.set("connect_tcp", lua.create_async_function(connect_tcp)?)?;
+    tbl.set("listen_unix", lua.make_fn(listen_unix)?)?;
+
+    Ok(())
+}
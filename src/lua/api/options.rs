@@ -0,0 +1,204 @@
+use bstr::{BString, ByteSlice};
+use anyhow::Result;
+use mlua::{prelude::*};
+use crate::lua::{LuaWrapper, FromLuaStr};
+use crate::shell::MetaString;
+use crate::ui::{Ui, options::{Kind, OptionDef, format_value}};
+
+fn unknown_option(name: &str) -> anyhow::Error {
+    anyhow::anyhow!("unknown option: {name}")
+}
+
+fn check(name: &str, kind: Kind, validate: Option<&LuaFunction>, value: &LuaValue) -> Result<()> {
+    if !kind.matches(value) {
+        anyhow::bail!("option {name} expects {kind}, got {}", value.type_name());
+    }
+    if let Some(validate) = validate {
+        let (ok, msg): (bool, Option<String>) = validate.call(value.clone())?;
+        if !ok {
+            anyhow::bail!("invalid value for option {name}: {}", msg.as_deref().unwrap_or("failed validation"));
+        }
+    }
+    Ok(())
+}
+
+async fn set_option(ui: &Ui, name: &str, value: LuaValue) -> Result<()> {
+    let (kind, validate, default) = {
+        let ui = ui.try_borrow()?;
+        let option = ui.options.get(name).ok_or_else(|| unknown_option(name))?;
+        (option.kind, option.validate.clone(), option.default.clone())
+    };
+
+    // nil goes back to the default
+    let is_set = !value.is_nil();
+    let value = if is_set {
+        check(name, kind, validate.as_ref(), &value)?;
+        value
+    } else {
+        default
+    };
+
+    if let Some(option) = ui.try_borrow_mut()?.options.get_mut(name) {
+        option.value = value;
+        option.is_set = is_set;
+    }
+    ui.event_callbacks.option_changed(ui, name).await
+}
+
+fn get_option_for_var(ui: &Ui, var: &BString) -> Result<Option<(String, Kind, LuaValue)>> {
+    let ui = ui.try_borrow()?;
+    let option = ui.options.iter().find(|option| option.var.as_ref() == Some(var));
+    Ok(option.map(|option| (option.name.clone(), option.kind, option.value.clone())))
+}
+
+fn find_option_for_var(ui: &Ui, var: &BString) -> Result<(String, Kind, LuaValue)> {
+    get_option_for_var(ui, var)?.ok_or_else(|| anyhow::anyhow!("no option for ${var}"))
+}
+
+fn parse_zsh_value(lua: &Lua, name: &str, kind: Kind, value: BString) -> Result<LuaValue> {
+    Ok(match kind {
+        Kind::Any | Kind::String => LuaValue::String(lua.create_string(&value)?),
+        Kind::Integer => LuaValue::Integer(value.to_str()?.trim().parse()?),
+        Kind::Number => LuaValue::Number(value.to_str()?.trim().parse()?),
+        Kind::Boolean => LuaValue::Boolean(match value.to_str()?.trim().to_lowercase().as_str() {
+            "1" | "true" | "yes" | "on" => true,
+            "" | "0" | "false" | "no" | "off" => false,
+            value => anyhow::bail!("option {name} expects boolean, got {value:?}"),
+        }),
+        Kind::Table | Kind::Function => anyhow::bail!("option {name} cannot be set from zsh"),
+    })
+}
+
+async fn set_from_zsh(ui: &Ui, var: &BString, value: Option<BString>) -> Result<()> {
+    let (name, kind, _) = find_option_for_var(ui, var)?;
+    let value = match value {
+        Some(value) => parse_zsh_value(&ui.lua, &name, kind, value)?,
+        None => LuaValue::Nil,
+    };
+    set_option(ui, &name, value).await
+}
+
+// the var looks up its option each time so that it survives the option being redefined
+fn create_var(ui: &Ui, var: &BString) -> Result<()> {
+    let weak = ui.downgrade();
+
+    macro_rules! make_setter {
+        (|$($arg:ident: $type:ty),*| $value:expr) => (
+            {
+                let weak = weak.clone();
+                let var = var.clone();
+                Box::new(move |$($arg: $type),*| {
+                    if let Ok(ui) = Ui::try_upgrade(&weak) {
+                        // this runs from a zsh assignment, so report it like zsh would
+                        let result = ui.shell_loop(false, set_from_zsh(&ui, &var, $value));
+                        if let Err(err) = result.and_then(|r| r) {
+                            ::log::error!("{}", err);
+                            eprintln!("{var}: {err}");
+                        }
+                    }
+                })
+            }
+        )
+    }
+
+    let get = {
+        let weak = weak.clone();
+        let var = var.clone();
+        Box::new(move || {
+            let value = Ui::try_upgrade(&weak).and_then(|ui| get_option_for_var(&ui, &var));
+            match value {
+                // e.g. the option is gone after a reload
+                Ok(None | Some((_, _, LuaValue::Nil))) => BString::default(),
+                Ok(Some((_, _, LuaValue::String(s)))) => s.as_bytes().to_vec().into(),
+                Ok(Some((_, _, value))) => format_value(&value).into(),
+                Err(err) => {
+                    ::log::error!("{}", err);
+                    BString::default()
+                },
+            }
+        })
+    };
+
+    ui.shell.create_dynamic_string_var(
+        MetaString::from(var.clone()).as_ref(),
+        get,
+        Some(make_setter!(|value: BString| Some(value))),
+        Some(make_setter!(|_explicit: bool| None)),
+    )
+}
+
+fn define(ui: &Ui, _lua: &Lua, opts: LuaTable) -> Result<()> {
+    let Some(name) = opts.get::<Option<String>>("name")? else {
+        anyhow::bail!("option needs a name");
+    };
+    let kind = opts.get::<Option<FromLuaStr<Kind>>>("type")?.map(|k| k.0).unwrap_or_default();
+    let default: LuaValue = opts.get("default")?;
+    let doc: Option<String> = opts.get("doc")?;
+    let validate: Option<LuaFunction> = opts.get("validate")?;
+    let var: Option<BString> = opts.get("var")?;
+
+    if !default.is_nil() {
+        check(&name, kind, validate.as_ref(), &default)?;
+    }
+
+    // being redefined, e.g. a plugin was reloaded
+    // so keep a value that was explicitly set if it is still valid
+    let old = ui.try_borrow()?.options.get(&name).filter(|option| option.is_set).map(|option| option.value.clone());
+    let old = old.filter(|value| check(&name, kind, validate.as_ref(), value).is_ok());
+
+    if let Some(var) = &var && !ui.try_borrow()?.options.has_var(var) {
+        create_var(ui, var)?;
+        ui.try_borrow_mut()?.options.add_var(var.clone());
+    }
+
+    ui.try_borrow_mut()?.options.define(OptionDef{
+        name,
+        kind,
+        is_set: old.is_some(),
+        value: old.unwrap_or_else(|| default.clone()),
+        default,
+        doc,
+        validate,
+        var,
+    });
+    Ok(())
+}
+
+async fn set(ui: Ui, _lua: Lua, (name, value): (String, LuaValue)) -> Result<()> {
+    set_option(&ui, &name, value).await
+}
+
+fn get(ui: &Ui, _lua: &Lua, name: String) -> Result<LuaValue> {
+    let ui = ui.try_borrow()?;
+    let option = ui.options.get(&name).ok_or_else(|| unknown_option(&name))?;
+    Ok(option.value.clone())
+}
+
+fn list(ui: &Ui, lua: &Lua, (): ()) -> Result<Vec<LuaTable>> {
+    let ui = ui.try_borrow()?;
+    let list = ui.options.iter().map(|option| {
+        let tbl = lua.create_table()?;
+        tbl.raw_set("name", option.name.as_str())?;
+        tbl.raw_set("type", option.kind.to_string())?;
+        tbl.raw_set("value", option.value.clone())?;
+        tbl.raw_set("default", option.default.clone())?;
+        tbl.raw_set("is_set", option.is_set)?;
+        tbl.raw_set("doc", option.doc.as_deref())?;
+        tbl.raw_set("var", option.var.as_ref().map(|var| lua.create_string(var)).transpose()?)?;
+        Ok(tbl)
+    }).collect::<LuaResult<_>>()?;
+    Ok(list)
+}
+
+pub fn init_lua(lua: &LuaWrapper) -> Result<()> {
+
+    let tbl = lua.create_table()?;
+    lua.api.set("opt", &tbl)?;
+
+    tbl.set("define", lua.make_fn(define)?)?;
+    tbl.set("set", lua.make_async_fn(set)?)?;
+    tbl.set("get", lua.make_fn(get)?)?;
+    tbl.set("list", lua.make_fn(list)?)?;
+
+    Ok(())
+}
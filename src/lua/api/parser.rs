@@ -3,6 +3,9 @@ use crate::ui::Ui;
 use anyhow::Result;
 use mlua::prelude::*;
 
+mod query;
+use query::SyntaxQuery;
+
 fn tokens_to_lua(tokens: &Vec<crate::shell::Token>, lua: &Lua) -> Result<LuaTable> {
     let tbl = lua.create_table_with_capacity(tokens.len(), 0)?;
     for token in tokens {
@@ -20,20 +23,98 @@ fn tokens_to_lua(tokens: &Vec<crate::shell::Token>, lua: &Lua) -> Result<LuaTabl
     Ok(tbl)
 }
 
-fn parse(ui: &Ui, lua: &Lua, (val, options): (bstr::BString, Option<LuaValue>)) -> Result<(bool, LuaTable)> {
+fn parse(ui: &Ui, lua: &Lua, (val, options): (bstr::BString, Option<LuaValue>)) -> Result<(bool, LuaTable, LuaValue)> {
     let options = if let Some(options) = options {
         lua.from_value(options)?
     } else {
         Default::default()
     };
-    let (complete, tokens) = ui.shell.parse(val, options);
+    let (complete, tokens) = ui.shell.parse(val.clone(), options);
+    let diagnostics = ui.shell.parse_diagnostics(val.as_ref(), complete, &tokens);
     let tokens = tokens_to_lua(&tokens, lua)?;
-    Ok((complete, tokens))
+    Ok((complete, tokens, lua.to_value(&diagnostics)?))
+}
+
+fn get_parse(ui: &Ui, lua: &Lua, (): ()) -> Result<(bool, LuaTable, mlua::String, LuaValue)> {
+    let tree = ui.get_parse_tree()?;
+    let tokens = tokens_to_lua(&tree.tokens, lua)?;
+    Ok((tree.complete, tokens, lua.create_string(&tree.contents)?, lua.to_value(&tree.diagnostics)?))
+}
+
+fn parse_commands(ui: &Ui, lua: &Lua, val: Option<bstr::BString>) -> Result<LuaValue> {
+    let commands = if let Some(val) = val {
+        let (_, tokens) = ui.shell.parse(val.clone(), Default::default());
+        ui.shell.parse_commands(val.as_ref(), &tokens)
+    } else {
+        // use the cached parse of the buffer
+        let tree = ui.get_parse_tree()?;
+        ui.shell.parse_commands(tree.contents.as_ref(), &tree.tokens)
+    };
+    Ok(lua.to_value_with(&commands, mlua::SerializeOptions::new().serialize_none_to_null(false))?)
+}
+
+fn compile_syntax_query(ui: &Ui, lua: &Lua, rules: LuaTable) -> Result<SyntaxQuery> {
+    // hl names refer to wish.style
+    let styles: Option<LuaTable> = ui.lua.api.get("style")?;
+    Ok(SyntaxQuery::compile(lua, styles.as_ref(), rules)?)
+}
+
+fn apply_syntax_query(ui: &Ui, lua: &Lua, (query, namespace): (LuaUserDataRef<SyntaxQuery>, usize)) -> Result<()> {
+    let tree = ui.get_parse_tree()?;
+    // hl names are looked up each time in case wish.style has changed
+    let styles: Option<LuaTable> = ui.lua.api.get("style")?;
+    let highlights = query.highlights(lua, styles.as_ref(), &tree.tokens, tree.contents.as_ref())?;
+
+    ui.queue_draw();
+    let mut ui = ui.try_borrow_mut()?;
+    ui.buffer.clear_highlights_in_namespace(namespace);
+    for hl in highlights {
+        ui.buffer.add_highlight(crate::tui::text::HighlightedRange{
+            parano: 0,
+            start: hl.range.start,
+            end: hl.range.end,
+            inner: crate::tui::text::Highlight{
+                style: hl.style,
+                namespace,
+                virtual_text: None,
+                conceal: None,
+                blend: hl.blend,
+                priority: hl.priority,
+            },
+        });
+    }
+    Ok(())
+}
+
+fn match_syntax_query(ui: &Ui, lua: &Lua, query: LuaUserDataRef<SyntaxQuery>) -> Result<LuaTable> {
+    let tree = ui.get_parse_tree()?;
+    let matches = query.matches(&tree.tokens, tree.contents.as_ref());
+
+    let tbl = lua.create_table_with_capacity(matches.len(), 0)?;
+    for m in matches {
+        let values = lua.create_table_with_capacity(m.len(), 0)?;
+        for (id, token) in m {
+            let t = lua.create_table()?;
+            t.raw_set("start", token.range.start + 1)?;
+            t.raw_set("finish", token.range.end)?;
+            if !token.kind.is_none() {
+                t.raw_set("kind", token.kind.to_string())?;
+            }
+            values.raw_push([LuaValue::Table(query.tables[id].clone()), LuaValue::Table(t)])?;
+        }
+        tbl.raw_push(values)?;
+    }
+    Ok(tbl)
 }
 
 pub fn init_lua(lua: &LuaWrapper) -> Result<()> {
 
     lua.set_fn("parse", parse)?;
+    lua.set_fn("get_parse", get_parse)?;
+    lua.set_fn("parse_commands", parse_commands)?;
+    lua.set_fn("compile_syntax_query", compile_syntax_query)?;
+    lua.set_fn("apply_syntax_query", apply_syntax_query)?;
+    lua.set_fn("match_syntax_query", match_syntax_query)?;
 
     Ok(())
 }
@@ -0,0 +1,364 @@
+use std::ops::Range;
+use bstr::BStr;
+use mlua::{prelude::*, UserData};
+use regex::bytes::Regex;
+use crate::shell::Token;
+use crate::tui::Style;
+use super::super::tui::BufferStyleOptions;
+
+#[derive(Debug, Clone, Copy)]
+enum Repeat {
+    Once,
+    Optional{ lazy: bool },
+    ZeroOrMore{ lazy: bool },
+    OneOrMore{ lazy: bool },
+    // anchors, these do not consume a token
+    Start,
+    End,
+}
+
+impl Repeat {
+    fn parse(value: Option<&str>) -> LuaResult<Self> {
+        Ok(match value {
+            None => Self::Once,
+            Some("?") => Self::Optional{ lazy: false },
+            Some("??") => Self::Optional{ lazy: true },
+            Some("*") => Self::ZeroOrMore{ lazy: false },
+            Some("*?") => Self::ZeroOrMore{ lazy: true },
+            Some("+") => Self::OneOrMore{ lazy: false },
+            Some("+?") => Self::OneOrMore{ lazy: true },
+            Some("^") => Self::Start,
+            Some("$") => Self::End,
+            Some(value) => return Err(crate::lua::lua_error(format!("unknown mod: {value:?}"))),
+        })
+    }
+
+    // whether this can match nothing, and if so if it prefers to
+    fn skippable(self) -> Option<bool> {
+        match self {
+            Self::Optional{ lazy } | Self::ZeroOrMore{ lazy } => Some(lazy),
+            _ => None,
+        }
+    }
+}
+
+struct Matcher {
+    // index into SyntaxQuery::tables
+    id: usize,
+    kind: Option<Regex>,
+    not_kind: Option<Regex>,
+    regex: Option<Regex>,
+    not_regex: Option<Regex>,
+    contains: Option<Vec<Matcher>>,
+    repeat: Repeat,
+    hl: Option<Hl>,
+    hlregex: Option<Regex>,
+}
+
+enum Hl {
+    // the style and whether to blend it
+    Style(Style, bool),
+    // index into SyntaxQuery::style_names, looked up in wish.style each time it is applied
+    // so that later changes to wish.style are picked up
+    Named(usize),
+}
+
+#[derive(Default)]
+struct CompileState {
+    tables: Vec<LuaTable>,
+    style_names: Vec<LuaString>,
+}
+
+struct Rule {
+    matchers: Vec<Matcher>,
+    priority: f64,
+}
+
+type Match<'a> = (&'a Matcher, &'a Token);
+
+pub struct QueryHighlight {
+    pub range: Range<usize>,
+    pub style: Style,
+    pub blend: bool,
+    pub priority: f64,
+}
+
+pub struct SyntaxQuery {
+    rules: Vec<Rule>,
+    // the original lua tables for each matcher
+    pub tables: Vec<LuaTable>,
+    style_names: Vec<LuaString>,
+}
+
+impl UserData for SyntaxQuery {}
+
+fn compile_regex(pattern: Option<String>, whole: bool) -> LuaResult<Option<Regex>> {
+    let Some(pattern) = pattern
+        else { return Ok(None) };
+    let pattern = if whole { format!("^({pattern})$") } else { pattern };
+    Regex::new(&pattern)
+        .map(Some)
+        .map_err(|e| crate::lua::lua_error(e.to_string()))
+}
+
+fn lookup_style(lua: &Lua, styles: Option<&LuaTable>, name: &LuaString) -> LuaResult<(Style, bool)> {
+    let style = match styles {
+        Some(styles) => styles.get::<LuaValue>(name.clone())?,
+        None => LuaValue::Nil,
+    };
+    if style.is_nil() {
+        return Err(crate::lua::lua_error(format!("unknown style: {:?}", name.to_string_lossy())))
+    }
+    Ok(BufferStyleOptions::from_lua(style, lua)?.into_style())
+}
+
+fn compile_matcher(lua: &Lua, styles: Option<&LuaTable>, table: LuaTable, state: &mut CompileState) -> LuaResult<Matcher> {
+    let contains = match table.get::<Option<LuaTable>>("contains")? {
+        Some(contains) => Some(compile_seq(lua, styles, contains, state)?),
+        None => None,
+    };
+
+    let hl = match table.get::<LuaValue>("hl")? {
+        LuaValue::Nil => None,
+        // a name in wish.style, checked now so that typos are caught early
+        LuaValue::String(name) => {
+            lookup_style(lua, styles, &name)?;
+            let index = match state.style_names.iter().position(|n| *n.as_bytes() == *name.as_bytes()) {
+                Some(index) => index,
+                None => {
+                    state.style_names.push(name);
+                    state.style_names.len() - 1
+                },
+            };
+            Some(Hl::Named(index))
+        },
+        value => {
+            let (style, blend) = BufferStyleOptions::from_lua(value, lua)?.into_style();
+            Some(Hl::Style(style, blend))
+        },
+    };
+
+    let matcher = Matcher{
+        id: state.tables.len(),
+        kind: compile_regex(table.get("kind")?, true)?,
+        not_kind: compile_regex(table.get("not_kind")?, true)?,
+        regex: compile_regex(table.get("regex")?, false)?,
+        not_regex: compile_regex(table.get("not_regex")?, false)?,
+        contains,
+        repeat: Repeat::parse(table.get::<Option<String>>("mod")?.as_deref())?,
+        hl,
+        hlregex: compile_regex(table.get("hlregex")?, false)?,
+    };
+    state.tables.push(table);
+    Ok(matcher)
+}
+
+fn compile_seq(lua: &Lua, styles: Option<&LuaTable>, seq: LuaTable, state: &mut CompileState) -> LuaResult<Vec<Matcher>> {
+    seq.sequence_values::<LuaTable>()
+        .map(|matcher| compile_matcher(lua, styles, matcher?, state))
+        .collect()
+}
+
+fn apply_matcher<'a>(matcher: &'a Matcher, token: &'a Token, cmd: &BStr) -> Option<Vec<Match<'a>>> {
+    if matcher.contains.is_some() && token.children.is_none() {
+        // matcher asserts child tokens but there aren't any
+        return None
+    }
+
+    if matcher.kind.is_some() || matcher.not_kind.is_some() {
+        let kind = token.kind.to_string();
+        if matcher.kind.as_ref().is_some_and(|r| !r.is_match(kind.as_bytes())) {
+            return None
+        }
+        if matcher.not_kind.as_ref().is_some_and(|r| r.is_match(kind.as_bytes())) {
+            return None
+        }
+    }
+
+    let tokstr = &cmd[token.range.clone()];
+    if matcher.regex.as_ref().is_some_and(|r| !r.is_match(tokstr)) {
+        return None
+    }
+    if matcher.not_regex.as_ref().is_some_and(|r| r.is_match(tokstr)) {
+        return None
+    }
+
+    let mut values = vec![(matcher, token)];
+    if let Some(contains) = &matcher.contains {
+        let children = token.children.as_deref().unwrap_or_default();
+        if !apply_seq(contains, children, cmd, &mut |matches| values.extend(matches)) {
+            return None
+        }
+    }
+    Some(values)
+}
+
+// try to apply seq[seq_index..] at tokens[token_index..] and return the end index
+fn apply_seq_at<'a>(
+    seq: &'a [Matcher],
+    mut seq_index: usize,
+    tokens: &'a [Token],
+    cmd: &BStr,
+    mut token_index: usize,
+) -> Option<(usize, Vec<Match<'a>>)> {
+
+    let mut values = vec![];
+    // fallback if the greedy match doesn't work out
+    let mut non_greedy = None;
+    let mut repeat = seq.get(seq_index).map_or(Repeat::Once, |m| m.repeat);
+
+    while let Some(matcher) = seq.get(seq_index) {
+        if let Some(lazy) = repeat.skippable()
+            && let Some((index, rest)) = apply_seq_at(seq, seq_index + 1, tokens, cmd, token_index)
+        {
+            let mut skipped = values.clone();
+            skipped.extend(rest);
+            if lazy {
+                return Some((index, skipped))
+            }
+            non_greedy = Some((index, skipped));
+        }
+
+        let token = tokens.get(token_index);
+        let next_matcher = match (repeat, token) {
+            (Repeat::End, Some(_)) => return non_greedy,
+            (Repeat::End, None) => true,
+            (Repeat::Start, _) if token_index != 0 => return non_greedy,
+            (Repeat::Start, _) => true,
+            // ran out of tokens before the end of the seq
+            (_, None) => return non_greedy,
+            (_, Some(token)) => {
+                let matches = apply_matcher(matcher, token, cmd);
+                let matched = matches.is_some();
+                if let Some(matches) = matches {
+                    values.extend(matches);
+                    token_index += 1;
+                }
+
+                match repeat {
+                    Repeat::ZeroOrMore{..} => !matched,
+                    Repeat::OneOrMore{ lazy } if matched => {
+                        repeat = Repeat::ZeroOrMore{ lazy };
+                        false
+                    },
+                    _ if matched => true,
+                    _ => return non_greedy,
+                }
+            },
+        };
+
+        if next_matcher {
+            seq_index += 1;
+            repeat = seq.get(seq_index).map_or(Repeat::Once, |m| m.repeat);
+        }
+    }
+    Some((token_index, values))
+}
+
+fn apply_seq<'a>(seq: &'a [Matcher], tokens: &'a [Token], cmd: &BStr, callback: &mut dyn FnMut(Vec<Match<'a>>)) -> bool {
+    let mut matched = false;
+    let mut token_index = 0;
+    while token_index < tokens.len() {
+        if let Some((end, values)) = apply_seq_at(seq, 0, tokens, cmd, token_index) {
+            matched = true;
+            // always make progress, even on an empty match
+            token_index = end.max(token_index + 1);
+            callback(values);
+        } else {
+            token_index += 1;
+        }
+    }
+    matched
+}
+
+// apply every rule at every depth of the tree
+fn apply_rules<'a>(
+    rules: &'a [Rule],
+    tokens: &'a [Token],
+    cmd: &BStr,
+    depth: usize,
+    callback: &mut dyn FnMut(usize, usize, Vec<Match<'a>>),
+) {
+    for (i, rule) in rules.iter().enumerate() {
+        apply_seq(&rule.matchers, tokens, cmd, &mut |values| callback(i, depth, values));
+    }
+    for token in tokens {
+        if let Some(children) = &token.children {
+            apply_rules(rules, children, cmd, depth + 1, callback);
+        }
+    }
+}
+
+impl SyntaxQuery {
+    pub fn compile(lua: &Lua, styles: Option<&LuaTable>, rules: LuaTable) -> LuaResult<Self> {
+        let mut state = CompileState::default();
+        let rules = rules.sequence_values::<LuaTable>()
+            .map(|rule| {
+                let rule = rule?;
+                let priority = rule.get::<Option<f64>>("priority")?.unwrap_or_default();
+                let matchers = compile_seq(lua, styles, rule, &mut state)?;
+                Ok(Rule{ matchers, priority })
+            })
+            .collect::<LuaResult<_>>()?;
+        Ok(Self{ rules, tables: state.tables, style_names: state.style_names })
+    }
+
+    // each match is a list of (matcher id, token)
+    pub fn matches<'a>(&'a self, tokens: &'a [Token], cmd: &BStr) -> Vec<Vec<(usize, &'a Token)>> {
+        let mut matches = vec![];
+        apply_rules(&self.rules, tokens, cmd, 0, &mut |_, _, values| {
+            matches.push(values.into_iter().map(|(m, t)| (m.id, t)).collect());
+        });
+        matches
+    }
+
+    // highlights sorted by priority
+    // deeper tokens and later rules win
+    pub fn highlights(&self, lua: &Lua, styles: Option<&LuaTable>, tokens: &[Token], cmd: &BStr) -> LuaResult<Vec<QueryHighlight>> {
+        let named_styles = self.style_names.iter()
+            .map(|name| lookup_style(lua, styles, name))
+            .collect::<LuaResult<Vec<_>>>()?;
+        let num_rules = self.rules.len() as f64;
+        let mut highlights = vec![];
+
+        apply_rules(&self.rules, tokens, cmd, 0, &mut |i, depth, values| {
+            let priority = (depth as f64 + self.rules[i].priority) * num_rules * 2. + (i + 1) as f64;
+            for (matcher, token) in values {
+                let (style, blend) = match &matcher.hl {
+                    Some(Hl::Style(style, blend)) => (style, blend),
+                    Some(Hl::Named(index)) => {
+                        let (style, blend) = &named_styles[*index];
+                        (style, blend)
+                    },
+                    None => continue,
+                };
+
+                let mut push = |range: Range<usize>| highlights.push(QueryHighlight{
+                    range,
+                    style: style.clone(),
+                    blend: *blend,
+                    priority,
+                });
+
+                if let Some(hlregex) = &matcher.hlregex {
+                    let start = token.range.start;
+                    for captures in hlregex.captures_iter(&cmd[token.range.clone()]) {
+                        // the first group if there is one, otherwise the whole match
+                        let m = captures.get(1).or_else(|| captures.get(0)).unwrap();
+                        push(start + m.start() .. start + m.end());
+                    }
+                } else {
+                    push(token.range.clone());
+                }
+            }
+        });
+
+        // break ties by order
+        let len = highlights.len() as f64;
+        for (i, hl) in highlights.iter_mut().enumerate() {
+            hl.priority += (i + 1) as f64 / len / 2.;
+        }
+        highlights.sort_by(|a, b| a.priority.total_cmp(&b.priority));
+        Ok(highlights)
+    }
+}
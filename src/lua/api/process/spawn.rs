@@ -13,7 +13,7 @@ use tokio::io::{
 use tokio::process::Command;
 use tokio::sync::{oneshot, watch, RwLock};
 use crate::ui::{Ui};
-use crate::lua::api::asyncio::{ReadableFile, WriteableFile};
+use crate::lua::api::asyncio::{ReadableFile, WriteableFile, run_cancellable};
 use super::subshell;
 
 #[derive(Debug, Copy, Clone)]
@@ -48,9 +48,9 @@ impl CommandResult {
 
 impl UserData for CommandResult {
     fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
-        methods.add_async_method("wait", |_lua, proc, ()| async move {
+        methods.add_async_method("wait", |_lua, proc, ()| run_cancellable(async move {
             proc.clone().wait().await
-        });
+        }));
     }
 }
 
@@ -67,9 +67,9 @@ impl UserData for Process {
             let cmd = proc.result.clone();
             lua.create_async_function(move |_lua, ()| {
                 let mut cmd = cmd.clone();
-                async move {
+                run_cancellable(async move {
                     Ok(cmd.wait().await)
-                }
+                })
             })
         });
     }
@@ -0,0 +1,223 @@
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use anyhow::Result;
+use bstr::BString;
+use mlua::prelude::*;
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use crate::lua::LuaWrapper;
+use crate::ui::Ui;
+use super::net::{bind_unix, Listener, SocketPath};
+
+const PARSE_ERROR: i32 = -32700;
+const INVALID_REQUEST: i32 = -32600;
+const METHOD_NOT_FOUND: i32 = -32601;
+const SERVER_ERROR: i32 = -32000;
+
+// rpc methods that just call through to the equivalent wish function
+const LUA_METHODS: &[(&str, &str)] = &[
+    ("get_buffer", "get_buffer"),
+    ("set_buffer", "set_buffer"),
+    ("get_cursor", "get_cursor"),
+    ("set_cursor", "set_cursor"),
+    ("set_message", "set_message"),
+    ("trigger_event", "trigger_event_callback"),
+    ("get_cwd", "get_cwd"),
+    ("get_history", "get_history"),
+];
+
+#[derive(Debug, Deserialize)]
+struct Request {
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Debug, Serialize)]
+struct RpcError {
+    code: i32,
+    message: String,
+}
+
+impl RpcError {
+    fn new<S: ToString>(code: i32, message: S) -> Self {
+        Self{ code, message: message.to_string() }
+    }
+}
+
+impl From<LuaError> for RpcError {
+    fn from(err: LuaError) -> Self {
+        Self::new(SERVER_ERROR, err)
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Response {
+    jsonrpc: &'static str,
+    id: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<RpcError>,
+}
+
+impl Response {
+    fn new(id: Value, result: Result<Value, RpcError>) -> Self {
+        let (result, error) = match result {
+            Ok(result) => (Some(result), None),
+            Err(error) => (None, Some(error)),
+        };
+        Self{ jsonrpc: "2.0", id, result, error }
+    }
+}
+
+fn default_socket_path() -> PathBuf {
+    let dir = if let Some(dir) = std::env::var_os("XDG_RUNTIME_DIR") {
+        PathBuf::from(dir).join("wish")
+    } else {
+        std::env::temp_dir().join(format!("wish-{}", nix::unistd::getuid()))
+    };
+    dir.join(format!("{}.sock", std::process::id()))
+}
+
+fn json_to_lua_args(lua: &Lua, params: Value) -> LuaResult<LuaMultiValue> {
+    let options = mlua::SerializeOptions::new()
+        .serialize_none_to_null(false)
+        .serialize_unit_to_null(false);
+    match params {
+        Value::Null => Ok(LuaMultiValue::new()),
+        // positional arguments
+        Value::Array(params) => params.iter().map(|p| lua.to_value_with(p, options)).collect(),
+        params => Ok(LuaMultiValue::from_iter([lua.to_value_with(&params, options)?])),
+    }
+}
+
+fn lua_to_json(lua: &Lua, values: LuaMultiValue) -> LuaResult<Value> {
+    let options = mlua::DeserializeOptions::new().deny_unsupported_types(false);
+    let values: LuaResult<Vec<Value>> = values.into_iter().map(|v| lua.from_value_with(v, options)).collect();
+    Ok(Value::Array(values?))
+}
+
+async fn call_method(ui: &Ui, method: &str, params: Value) -> Result<Value, RpcError> {
+    let lua = &ui.lua;
+
+    let (func, args) = if method == "lua" {
+        let code = match params {
+            Value::String(code) => code,
+            Value::Array(mut params) if params.first().is_some_and(|p| p.is_string()) => {
+                let Value::String(code) = params.remove(0) else { unreachable!() };
+                code
+            },
+            _ => return Err(RpcError::new(INVALID_REQUEST, "expected lua code as a string")),
+        };
+        (lua.load(code).set_name("=remote").into_function()?, LuaMultiValue::new())
+    } else if let Some((_, name)) = LUA_METHODS.iter().find(|(m, _)| *m == method) {
+        (lua.api.get::<LuaFunction>(*name)?, json_to_lua_args(lua, params)?)
+    } else {
+        return Err(RpcError::new(METHOD_NOT_FOUND, format!("unknown method: {method:?}")))
+    };
+
+    // run at the same point as wish.schedule callbacks
+    ui.queue_scheduled_callbacks();
+    ui.scheduled_callback_notify.notified().await;
+    let result = crate::lua::call_lua_fn(&func, args).await?;
+    ui.queue_draw();
+    Ok(lua_to_json(lua, result)?)
+}
+
+async fn handle_line(ui: &Ui, line: &[u8]) -> Option<Response> {
+    let request: Request = match serde_json::from_slice(line) {
+        Ok(request) => request,
+        Err(err) => return Some(Response::new(Value::Null, Err(RpcError::new(PARSE_ERROR, err)))),
+    };
+
+    let result = call_method(ui, &request.method, request.params).await;
+    // no id means it is a notification and wants no response
+    request.id.map(|id| Response::new(id, result))
+}
+
+async fn handle_connection(ui: Ui, stream: UnixStream) -> Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let mut line = vec![];
+
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line).await? == 0 {
+            return Ok(())
+        }
+        if line.trim_ascii().is_empty() {
+            continue
+        }
+
+        if let Some(response) = handle_line(&ui, &line).await {
+            let mut buf = serde_json::to_vec(&response)?;
+            buf.push(b'\n');
+            writer.write_all(&buf).await?;
+        }
+    }
+}
+
+async fn run_server(ui: Ui, listener: UnixListener, _path: SocketPath) -> Result<()> {
+    // _path is dropped, and the socket removed, when this is cancelled
+    loop {
+        let (stream, _) = listener.accept().await?;
+        // move to an fd >= 10
+        let stream = UnixStream::from_std(crate::utils::move_fd(stream.into_std()?)?)?;
+        crate::spawn_and_log(&ui, handle_connection(ui.clone(), stream));
+    }
+}
+
+// anyone who can connect can run lua, so the directory must be ours alone
+// e.g. someone else may have created it first in /tmp
+fn check_private_dir(dir: &Path) -> Result<()> {
+    let meta = std::fs::symlink_metadata(dir)?;
+    if !meta.file_type().is_dir() {
+        anyhow::bail!("{dir:?} is not a directory");
+    }
+    if meta.uid() != nix::unistd::getuid().as_raw() {
+        anyhow::bail!("{dir:?} is not owned by the current user");
+    }
+    if meta.mode() & 0o777 != 0o700 {
+        anyhow::bail!("{dir:?} must have permissions 0700, got {:04o}", meta.mode() & 0o777);
+    }
+    Ok(())
+}
+
+fn listen(ui: &Ui, _lua: &Lua, path: Option<BString>) -> Result<Listener> {
+    let path = match path {
+        Some(path) => super::fs::to_path(&path).to_owned(),
+        None => default_socket_path(),
+    };
+
+    if let Some(dir) = path.parent() {
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)?;
+        check_private_dir(dir)?;
+    }
+
+    // create the socket as 0600 so that no one else can connect before the chmod
+    let old_umask = nix::sys::stat::umask(nix::sys::stat::Mode::from_bits_truncate(0o177));
+    let result = bind_unix(ui, &path);
+    nix::sys::stat::umask(old_umask);
+    let (listener, path) = result?;
+    // don't leave this up to the umask
+    std::fs::set_permissions(&path.0, std::fs::Permissions::from_mode(0o600))?;
+    let ui_clone = ui.clone();
+    Listener::spawn(ui, path, move |path| run_server(ui_clone, listener, path))
+}
+
+pub fn init_lua(lua: &LuaWrapper) -> Result<()> {
+
+    let tbl = lua.create_table()?;
+    lua.api.set("remote", &tbl)?;
+
+    tbl.set("listen", lua.make_fn(listen)?)?;
+
+    Ok(())
+}
@@ -8,7 +8,7 @@ use crossterm::{
     cursor::SetCursorStyle,
 };
 use mlua::{prelude::*};
-use crate::ui::{Ui};
+use crate::ui::{Ui, TransientPrompt, buffer::marks::{Mark, Gravity}};
 use crate::tui::{
     self,
     layout::{self, Node, NodeKind, Layout, NodeId},
@@ -19,6 +19,8 @@ use crate::tui::{
     text::Alignment,
     Cell,
     sizing,
+    command_line::{PromptMode, RightPromptMode},
+    status_bar::{StatusBarContents, Bar, Placement, DEFAULT_NAME},
 };
 use crate::tui::border;
 
@@ -450,13 +452,21 @@ impl From<Style> for StyleOptions {
 
 auto_from_lua! {
     #[derive(Debug, Default)]
-    struct BufferStyleOptions {
+    pub(super) struct BufferStyleOptions {
         #[flatten]
         inner: StyleOptions,
         no_blend: Option<bool>,
     }
 }
 
+impl BufferStyleOptions {
+    // returns the style and whether to blend it
+    pub(super) fn into_style(self) -> (Style, bool) {
+        let blend = !self.no_blend.unwrap_or_default();
+        (self.inner.into(), blend)
+    }
+}
+
 fn parse_line<T: Default+Clone>(line: LineOptions, text: &mut tui::text::Text<T>) {
     match line {
         LineOptions::Unstyled(string) => {
@@ -592,7 +602,10 @@ fn process_message(tui: &mut tui::Tui, options: MessageOptions) -> Result<&mut N
                     LayoutChild::Message(child_options) => {
                         let child = process_message(tui, child_options)?;
                         child.has_parent = true;
-                        layout.children.push(child.id);
+                        let id = child.id;
+                        // new nodes start off at the top level
+                        tui.nodes.remove_child_from_parent(id);
+                        layout.children.push(id);
                     },
                     LayoutChild::WidgetRef(id) => {
                         let id = NodeId::Normal(id);
@@ -746,9 +759,8 @@ auto_from_lua! {
 }
 
 fn add_buf_highlight(ui: &Ui, _lua: &Lua, val: BufferHighlight) -> Result<()> {
-    let blend = !val.style.no_blend.unwrap_or_default();
     let priority = val.priority.unwrap_or_default();
-    let style: Style = val.style.inner.into();
+    let (style, blend) = val.style.into_style();
 
     ui.try_borrow_mut()?.buffer.add_highlight(tui::text::HighlightedRange{
         parano: 0,
@@ -785,6 +797,118 @@ fn add_buf_highlight_namespace(ui: &Ui, _lua: &Lua, _val: ()) -> Result<usize> {
     Ok(ui.buffer.highlight_counter)
 }
 
+auto_from_lua! {
+    #[derive(Debug, Default)]
+    struct MarkHighlight {
+        #[flatten]
+        style: BufferStyleOptions,
+        virtual_text: Option<BString>,
+        conceal: Option<bool>,
+        priority: Option<f64>,
+    }
+}
+
+auto_from_lua! {
+    #[derive(Debug, Default)]
+    struct MarkOptions {
+        start: super::number::PossiblyMaxUsize,
+        finish: Option<super::number::PossiblyMaxUsize>,
+        namespace: Option<usize>,
+        gravity: Option<FromLuaStr<Gravity>>,
+        data: Option<LuaValue>,
+        highlight: Option<MarkHighlight>,
+    }
+}
+
+fn set_mark(ui: &Ui, _lua: &Lua, val: MarkOptions) -> Result<usize> {
+    let namespace = val.namespace.unwrap_or(0);
+    let highlight = val.highlight.map(|hl| {
+        let (style, blend) = hl.style.into_style();
+        tui::text::Highlight{
+            style,
+            namespace,
+            virtual_text: hl.virtual_text.map(std::borrow::Cow::Owned),
+            conceal: hl.conceal,
+            blend,
+            priority: hl.priority.unwrap_or_default(),
+        }
+    });
+    let has_highlight = highlight.is_some();
+
+    let id = {
+        let mut ui = ui.try_borrow_mut()?;
+        let len = ui.buffer.get_contents().len();
+        let start = usize::from(val.start).saturating_sub(1).min(len);
+        // no finish means a point mark
+        let end = val.finish.map_or(start, usize::from).clamp(start, len);
+        ui.buffer.dirty |= has_highlight;
+        ui.buffer.marks.add(Mark{
+            start,
+            end,
+            namespace,
+            gravity: val.gravity.map(|g| g.0).unwrap_or_default(),
+            data: val.data,
+            highlight,
+        })
+    };
+
+    if has_highlight {
+        ui.queue_draw();
+    }
+    Ok(id)
+}
+
+fn mark_to_lua(lua: &Lua, id: usize, mark: &Mark) -> LuaResult<LuaTable> {
+    let tbl = lua.create_table()?;
+    tbl.raw_set("id", id)?;
+    tbl.raw_set("start", mark.start + 1)?;
+    tbl.raw_set("finish", mark.end)?;
+    tbl.raw_set("namespace", mark.namespace)?;
+    tbl.raw_set("data", mark.data.clone())?;
+    Ok(tbl)
+}
+
+fn get_mark(ui: &Ui, lua: &Lua, id: usize) -> Result<Option<LuaTable>> {
+    let ui = ui.try_borrow()?;
+    Ok(ui.buffer.marks.get(id).map(|mark| mark_to_lua(lua, id, mark)).transpose()?)
+}
+
+auto_from_lua! {
+    #[derive(Debug, Default)]
+    struct MarkRange {
+        start: super::number::PossiblyMaxUsize,
+        finish: super::number::PossiblyMaxUsize,
+    }
+}
+
+fn get_marks(ui: &Ui, lua: &Lua, (namespace, range): (Option<usize>, Option<MarkRange>)) -> Result<Vec<LuaTable>> {
+    let ui = ui.try_borrow()?;
+    let range = range.map(|r| usize::from(r.start).saturating_sub(1) .. r.finish.into());
+    let marks = ui.buffer.marks.iter(namespace, range)
+        .map(|(id, mark)| mark_to_lua(lua, id, mark))
+        .collect::<LuaResult<_>>()?;
+    Ok(marks)
+}
+
+fn del_mark(ui: &Ui, _lua: &Lua, id: usize) -> Result<bool> {
+    let Some(mark) = ui.try_borrow_mut()?.buffer.marks.remove(id) else {
+        return Ok(false)
+    };
+    if mark.highlight.is_some() {
+        ui.try_borrow_mut()?.buffer.dirty = true;
+        ui.queue_draw();
+    }
+    Ok(true)
+}
+
+fn clear_marks(ui: &Ui, _lua: &Lua, namespace: Option<usize>) -> Result<()> {
+    ui.queue_draw();
+    let mut ui = ui.try_borrow_mut()?;
+    ui.buffer.marks.clear_namespace(namespace);
+    ui.buffer.dirty = true;
+    Ok(())
+}
+
 fn scroll_message(ui: &Ui, _lua: &Lua, (id, delta): (usize, isize)) -> Result<()> {
     let tui = &mut ui.try_borrow_mut()?.tui;
     let id = NodeId::Normal(id);
@@ -866,49 +990,167 @@ fn message_to_ansi_string(ui: &Ui, _lua: &Lua, (id, width): (usize, Option<u16>)
     }
 }
 
-fn set_status_bar(ui: &Ui, _lua: &Lua, val: Option<MessageOptions>) -> Result<()> {
+// layouts and existing messages are drawn from the message tree
+// so their children can be updated with set_message
+fn is_prompt_node(options: &MessageOptions) -> bool {
+    options.id.is_some() || matches!(options.inner, MessageInner::Layout{..})
+}
+
+fn make_prompt_node(tui: &mut tui::Tui, options: MessageOptions) -> Result<NodeId> {
+    // an existing message belongs to the caller
+    let owned = options.id.is_none();
+    let id = process_message(tui, options)?.id;
+    tui.nodes.detach(id, owned);
+    tui.dirty = true;
+    Ok(id)
+}
+
+// let go of the node that was previously used, if it has been replaced
+fn release_prompt_node(tui: &mut tui::Tui, old: Option<NodeId>, new: Option<NodeId>) {
+    if let Some(old) = old && Some(old) != new {
+        tui.nodes.release(old);
+        tui.dirty = true;
+    }
+}
+
+auto_from_lua! {
+    #[derive(Debug, Default)]
+    struct StatusBarOptions {
+        name: Option<String>,
+        placement: Option<FromLuaStr<Placement>>,
+        #[flatten]
+        message: Option<MessageOptions>,
+    }
+}
+
+fn set_status_bar(ui: &Ui, _lua: &Lua, val: Option<StatusBarOptions>) -> Result<Option<usize>> {
     ui.queue_draw();
-    let mut ui = ui.try_borrow_mut()?;
-    if let Some(options) = val {
-        match options.inner {
-            MessageInner::Widget { style, contents } => {
-                let widget = ui.status_bar.inner.get_or_insert_default();
-                if let Some(contents) = contents {
-                    widget.inner.clear();
-                    parse_text_parts(contents, &mut widget.inner);
-                }
-                // StatusBar is standalone, node options (persist/hidden/constraint) are ignored
-                set_widget_options(widget, style);
-            },
-            MessageInner::Layout { .. } => anyhow::bail!("status bar only accepts widget options"),
+    let ui = &mut *ui.try_borrow_mut()?;
+
+    let Some(options) = val else {
+        if let Some(bar) = ui.status_bar.remove(DEFAULT_NAME) {
+            release_prompt_node(&mut ui.tui, bar.get_layout_id(), None);
+        }
+        return Ok(None)
+    };
+
+    let name = options.name.unwrap_or_else(|| DEFAULT_NAME.to_owned());
+    if ui.status_bar.get(&name).is_none() {
+        let contents = StatusBarContents::Widget(Default::default());
+        ui.status_bar.set(Bar::new(name.clone(), contents, Placement::default()));
+    }
+    let bar = ui.status_bar.get_mut(&name).unwrap();
+    let old = bar.get_layout_id();
+
+    if let Some(options) = options.message {
+        if is_prompt_node(&options) {
+            bar.contents = StatusBarContents::Layout(make_prompt_node(&mut ui.tui, options)?);
+        } else if let MessageInner::Widget { style, contents } = options.inner
+            // e.g. only the placement is being changed
+            && !(style.is_none() && contents.is_none())
+        {
+            if !matches!(bar.contents, StatusBarContents::Widget(_)) {
+                bar.contents = StatusBarContents::Widget(Default::default());
+            }
+            let StatusBarContents::Widget(widget) = &mut bar.contents
+                else { unreachable!() };
+            if let Some(contents) = contents {
+                widget.inner.clear();
+                parse_text_parts(contents, &mut widget.inner);
+            }
+            // StatusBar is standalone, node options (persist/hidden/constraint) are ignored
+            set_widget_options(widget, style);
         }
     }
+    if let Some(placement) = options.placement {
+        bar.placement = placement.0;
+    }
+
+    let new = bar.get_layout_id();
+    release_prompt_node(&mut ui.tui, old, new);
     ui.status_bar.dirty = true;
-    Ok(())
+    Ok(new.map(usize::from))
 }
 
-fn set_prompt(ui: &Ui, __lua: &Lua, val: Option<MessageOptions>) -> Result<()> {
+fn remove_status_bar(ui: &Ui, _lua: &Lua, name: Option<String>) -> Result<bool> {
     ui.queue_draw();
-    let mut ui = ui.try_borrow_mut()?;
-    if let Some(options) = val {
-        match options.inner {
-            MessageInner::Widget { style, contents } => {
-                let mut widget = tui::widget::Widget::default();
-                if let Some(contents) = contents {
-                    parse_text_parts(contents, &mut widget.inner);
-                }
-                set_widget_options(&mut widget, style);
-                ui.cmdline.prompt_mode = tui::command_line::PromptMode::Custom{widget};
-            },
-            MessageInner::Layout { .. } => anyhow::bail!("prompt only accepts widget options"),
-        }
+    let ui = &mut *ui.try_borrow_mut()?;
+    let name = name.as_deref().unwrap_or(DEFAULT_NAME);
+    if let Some(bar) = ui.status_bar.remove(name) {
+        release_prompt_node(&mut ui.tui, bar.get_layout_id(), None);
+        Ok(true)
     } else {
-        ui.cmdline.prompt_mode = tui::command_line::PromptMode::ShellVars(Default::default());
+        Ok(false)
     }
+}
+
+fn get_status_bars(ui: &Ui, lua: &Lua, (): ()) -> Result<Vec<LuaTable>> {
+    let ui = ui.try_borrow()?;
+    let list = ui.status_bar.iter().map(|bar| {
+        let tbl = lua.create_table()?;
+        tbl.raw_set("name", bar.name.as_str())?;
+        tbl.raw_set("placement", bar.placement.to_string())?;
+        tbl.raw_set("id", bar.get_layout_id().map(usize::from))?;
+        Ok(tbl)
+    }).collect::<LuaResult<_>>()?;
+    Ok(list)
+}
+
+fn make_prompt_widget(options: MessageOptions, name: &str) -> Result<tui::widget::Widget> {
+    match options.inner {
+        MessageInner::Widget { style, contents } => {
+            let mut widget = tui::widget::Widget::default();
+            if let Some(contents) = contents {
+                parse_text_parts(contents, &mut widget.inner);
+            }
+            set_widget_options(&mut widget, style);
+            Ok(widget)
+        },
+        MessageInner::Layout { .. } => anyhow::bail!("{name} only accepts widget options"),
+    }
+}
+
+fn set_prompt(ui: &Ui, __lua: &Lua, val: Option<MessageOptions>) -> Result<Option<usize>> {
+    ui.queue_draw();
+    let mut ui = ui.try_borrow_mut()?;
+    let old = ui.cmdline.prompt_mode.get_layout_id();
+    let mode = match val {
+        Some(options) if is_prompt_node(&options) => PromptMode::Layout{id: make_prompt_node(&mut ui.tui, options)?},
+        Some(options) => PromptMode::Custom{widget: make_prompt_widget(options, "prompt")?},
+        None => PromptMode::ShellVars(Default::default()),
+    };
+    ui.cmdline.prompt_mode = mode;
+    let new = ui.cmdline.prompt_mode.get_layout_id();
+    release_prompt_node(&mut ui.tui, old, new);
     ui.cmdline.prompt_dirty = true;
+    Ok(new.map(usize::from))
+}
+
+auto_from_lua! {
+    #[derive(Debug)]
+    enum TransientPromptOptions {
+        Callback(LuaFunction),
+        Widget(MessageOptions),
+    }
+}
+
+// replaces the prompt of accepted commands
+fn set_transient_prompt(ui: &Ui, __lua: &Lua, val: Option<TransientPromptOptions>) -> Result<()> {
+    let transient = match val {
+        Some(TransientPromptOptions::Callback(func)) => Some(TransientPrompt::Callback(func)),
+        Some(TransientPromptOptions::Widget(options)) => Some(TransientPrompt::Widget(make_prompt_widget(options, "transient prompt")?)),
+        None => None,
+    };
+    ui.try_borrow_mut()?.transient_prompt = transient;
     Ok(())
 }
 
+// converts what a transient prompt callback returned
+pub fn make_transient_prompt(lua: &Lua, value: LuaValue) -> Result<Option<tui::widget::Widget>> {
+    let options: Option<MessageOptions> = FromLua::from_lua(value, lua)?;
+    options.map(|options| make_prompt_widget(options, "transient prompt")).transpose()
+}
+
 auto_from_lua! {
     #[derive(Debug, Default)]
     struct RPromptOptions {
@@ -918,29 +1160,31 @@ auto_from_lua! {
     }
 }
 
-fn set_rprompt(ui: &Ui, __lua: &Lua, val: Option<RPromptOptions>) -> Result<()> {
+fn set_rprompt(ui: &Ui, __lua: &Lua, val: Option<RPromptOptions>) -> Result<Option<usize>> {
     ui.queue_draw();
     let mut ui = ui.try_borrow_mut()?;
-    if let Some(options) = val && let Some(message) = options.message {
-        match message.inner {
-            MessageInner::Widget { style, contents } => {
-                let mut widget = tui::widget::Widget::default();
-                if let Some(contents) = contents {
-                    parse_text_parts(contents, &mut widget.inner);
-                }
-                set_widget_options(&mut widget, style);
-                ui.cmdline.rprompt_mode = tui::command_line::RightPromptMode::Custom{
-                    widget,
-                    auto_disappear: options.auto_disappear.unwrap_or(true),
-                };
-            },
-            MessageInner::Layout { .. } => anyhow::bail!("rprompt only accepts widget options"),
+    let old = ui.cmdline.rprompt_mode.get_layout_id();
+    let mode = if let Some(options) = val && let Some(message) = options.message {
+        let auto_disappear = options.auto_disappear.unwrap_or(true);
+        if is_prompt_node(&message) {
+            RightPromptMode::Layout{
+                id: make_prompt_node(&mut ui.tui, message)?,
+                auto_disappear,
+            }
+        } else {
+            RightPromptMode::Custom{
+                widget: make_prompt_widget(message, "rprompt")?,
+                auto_disappear,
+            }
         }
     } else {
-        ui.cmdline.rprompt_mode = tui::command_line::RightPromptMode::ShellVars(Default::default());
-    }
+        RightPromptMode::ShellVars(Default::default())
+    };
+    ui.cmdline.rprompt_mode = mode;
+    let new = ui.cmdline.rprompt_mode.get_layout_id();
+    release_prompt_node(&mut ui.tui, old, new);
     ui.cmdline.rprompt_dirty = true;
-    Ok(())
+    Ok(new.map(usize::from))
 }
 
 auto_from_lua! {
@@ -1019,9 +1263,10 @@ fn get_message_geometry(ui: &Ui, lua: &Lua, id: usize) -> Result<Option<LuaTable
     }
 }
 
-fn get_status_bar_geometry(ui: &Ui, lua: &Lua, (): ()) -> Result<Option<LuaTable>> {
+fn get_status_bar_geometry(ui: &Ui, lua: &Lua, name: Option<String>) -> Result<Option<LuaTable>> {
     let ui = ui.try_borrow()?;
-    if let Some(geom) = ui.tui.get_status_bar_geometry(&ui.status_bar) {
+    let name = name.as_deref().unwrap_or(DEFAULT_NAME);
+    if let Some(geom) = ui.tui.get_status_bar_geometry(&ui.status_bar, name) {
         let table = lua.create_table_from([
             ("x", geom.x),
             ("y", geom.y),
@@ -1034,6 +1279,48 @@ fn get_status_bar_geometry(ui: &Ui, lua: &Lua, (): ()) -> Result<Option<LuaTable
     }
 }
 
+fn get_draw_stats(ui: &Ui, lua: &Lua, (): ()) -> Result<LuaTable> {
+    let stats = ui.try_borrow()?.tui.stats;
+    let frames = ui.frame_scheduler.get_stats();
+    let table = lua.create_table_from([
+        ("frames", frames.frames),
+        ("requests", frames.requests),
+        ("coalesced", frames.coalesced),
+        ("last_frame_bytes", stats.last_frame_bytes),
+        ("total_bytes", stats.total_bytes),
+    ])?;
+    // in seconds
+    table.raw_set("last_frame_time", frames.last_frame_time.as_secs_f64())?;
+    table.raw_set("max_frame_time", frames.max_frame_time.as_secs_f64())?;
+    table.raw_set("avg_frame_time", frames.total_frame_time.as_secs_f64() / frames.frames.max(1) as f64)?;
+    Ok(table)
+}
+
+auto_from_lua! {
+    #[derive(Debug, Default)]
+    struct DrawOptions {
+        max_fps: Option<f64>,
+        idle_fps: Option<f64>,
+    }
+}
+
+fn set_draw_options(ui: &Ui, _lua: &Lua, val: DrawOptions) -> Result<()> {
+    let mut options = ui.frame_scheduler.options.get();
+    for (name, fps, field) in [("max_fps", val.max_fps, &mut options.max_fps), ("idle_fps", val.idle_fps, &mut options.idle_fps)] {
+        if let Some(fps) = fps {
+            if fps.is_nan() || fps < 0. {
+                anyhow::bail!("{name} must be a non negative number, got {fps}");
+            }
+            if fps > 0. && std::time::Duration::try_from_secs_f64(1. / fps).is_err() {
+                anyhow::bail!("{name} is too small, got {fps}");
+            }
+            *field = fps;
+        }
+    }
+    ui.frame_scheduler.options.set(options);
+    Ok(())
+}
+
 fn get_cursor_pos(ui: &Ui, _lua: &Lua, (): ()) -> Result<(u16, u16)> {
     Ok(ui.try_borrow()?.cmdline.cursor_coord)
 }
@@ -1094,17 +1381,27 @@ pub fn init_lua(lua: &LuaWrapper) -> Result<()> {
     lua.set_fn("add_buf_highlight_namespace", add_buf_highlight_namespace)?;
     lua.set_fn("add_buf_highlight", add_buf_highlight)?;
     lua.set_fn("clear_buf_highlights", clear_buf_highlights)?;
+    lua.set_fn("set_mark", set_mark)?;
+    lua.set_fn("get_mark", get_mark)?;
+    lua.set_fn("get_marks", get_marks)?;
+    lua.set_fn("del_mark", del_mark)?;
+    lua.set_fn("clear_marks", clear_marks)?;
     lua.set_fn("feed_ansi_message", feed_ansi_message)?;
     lua.set_fn("clear_message", clear_message)?;
     lua.set_fn("get_message_text", get_message_text)?;
     lua.set_fn("message_to_ansi_string", message_to_ansi_string)?;
     lua.set_fn("set_status_bar", set_status_bar)?;
+    lua.set_fn("remove_status_bar", remove_status_bar)?;
+    lua.set_fn("get_status_bars", get_status_bars)?;
     lua.set_fn("set_prompt", set_prompt)?;
     lua.set_fn("set_rprompt", set_rprompt)?;
+    lua.set_fn("set_transient_prompt", set_transient_prompt)?;
     lua.set_async_fn("enable_mouse_mode", enable_mouse_mode)?;
     lua.set_fn("get_message_geometry", get_message_geometry)?;
     lua.set_fn("get_status_bar_geometry", get_status_bar_geometry)?;
     lua.set_fn("get_cursor_pos", get_cursor_pos)?;
+    lua.set_fn("get_draw_stats", get_draw_stats)?;
+    lua.set_fn("set_draw_options", set_draw_options)?;
     lua.set_async_fn("set_cursor_style", set_cursor_style)?;
     lua.set_fn("add_render_callback", add_render_callback)?;
     lua.set_fn("remove_render_callback", remove_render_callback)?;
@@ -24,7 +24,7 @@ pub use zsh::{
     variables,
     signals,
     functions::Function,
-    parser::{Token, ParserOptions},
+    parser::{Token, ParserOptions, ParseTree, SimpleCommand, Diagnostic},
     ZptyOpts,
     Zpty,
     set_zpty_size,
@@ -64,6 +64,7 @@ pub struct Shell {
     trampoline: RefCell<Vec<Option<Trampoline>>>,
     accept_line_trampoline: Cell<Option<Trampoline<Option<BString>>>>,
     sink: RefCell<file_stream::Sink>,
+    err_sink: RefCell<file_stream::Sink>,
 }
 
 pub enum KeybindValue {
@@ -130,6 +131,7 @@ impl Shell {
     pub fn new() -> Self {
         Shell {
             sink: RefCell::new(file_stream::Sink::new().unwrap()),
+            err_sink: RefCell::new(file_stream::Sink::new().unwrap()),
             trampoline: RefCell::new(vec![None]),
             accept_line_trampoline: Cell::new(None),
         }
@@ -297,9 +299,13 @@ impl Shell {
         _token: TrampolineToken,
         line: BString,
         callback: Box<dyn FnMut(std::iter::Peekable<zsh::completion::MatchIter>) -> ControlFlow<()>>,
-    ) -> Option<BString> {
+    ) -> (Option<BString>, Option<BString>) {
         // this may block for a long time
-        self.capture_shout(false, true, || zsh::completion::get_completions(line, callback)).0
+        // zerr, zwarning go to stderr so return them separately from the output
+        let (errors, (output, ())) = self.capture_stderr(false, true, || {
+            self.capture_shout(false, true, || zsh::completion::get_completions(line, callback))
+        });
+        (output, errors)
     }
 
     pub fn insert_completion(&self, string: BString, m: &completion::Match) -> (BString, usize) {
@@ -310,6 +316,36 @@ impl Shell {
         zsh::parser::parse(string, options)
     }
 
+    pub fn parse_commands(&self, string: &BStr, tokens: &[zsh::parser::Token]) -> Vec<SimpleCommand> {
+        zsh::parser::extract_commands(string, tokens)
+    }
+
+    pub fn resolve_command(&self, name: &MetaStr) -> zsh::resolve::ResolvedCommand {
+        zsh::resolve::resolve_command(name)
+    }
+
+    pub fn parse_diagnostics(&self, string: &BStr, complete: bool, tokens: &[zsh::parser::Token]) -> Vec<Diagnostic> {
+        let has_error = tokens.iter().any(|t| matches!(t.kind, zsh::parser::TokenKind::SyntaxError));
+        let messages = if complete && !has_error {
+            None
+        } else {
+            self.capture_stderr(false, true, || zsh::parser::print_syntax_errors(string)).0
+        };
+        zsh::parser::make_diagnostics(string, complete, tokens, messages)
+    }
+
+    pub fn update_parse_tree(&self, tree: Option<&ParseTree>, string: BString) -> (ParseTree, bool) {
+        let options = zsh::parser::ParserOptions::default();
+        let (mut tree, changed) = match tree {
+            Some(tree) => tree.update(string, options),
+            None => (ParseTree::new(string, options), true),
+        };
+        if changed {
+            tree.diagnostics = self.parse_diagnostics(tree.contents.as_ref(), tree.complete, &tree.tokens);
+        }
+        (tree, changed)
+    }
+
     pub fn get_prompt(&self, prompt: Option<&MetaStr>, escaped: bool) -> Option<MetaString> {
         zsh::get_prompt(prompt, escaped)
     }
@@ -491,6 +527,27 @@ impl Shell {
         }
     }
 
+    pub fn expand(&self, word: BString, options: zsh::expand::ExpandOptions) -> Result<Vec<BString>> {
+        let word = if options.history && word.contains(&b'!') {
+            // this goes through the zle buffer, so put it back afterwards
+            let (buffer, cursor) = self.get_zle_buffer();
+            let expanded = self.expandhistory(word.clone());
+            self.set_zle_buffer(buffer, cursor.unwrap_or(0));
+            expanded.unwrap_or(word)
+        } else {
+            word
+        };
+
+        let word = MetaString::from(word);
+        let (errors, result) = self.capture_stderr(false, true, || zsh::expand::expand(word.as_ref(), options));
+        // use the zsh error message if there is one
+        result.map_err(|err| {
+            errors.as_ref()
+                .and_then(|e| e.lines().map(zsh::strip_error_prefix).find(|l| !l.is_empty()))
+                .map_or(err, |msg| anyhow::anyhow!("{}", msg.as_bstr()))
+        })
+    }
+
     pub fn get_cwd(&self) -> BString {
         unsafe {
             MetaStr::from_ptr(zsh_sys::pwd).unmetafy().into_owned()
@@ -579,13 +636,32 @@ impl Shell {
         capture: bool,
         f: F,
     ) -> (Option<BString>, T) {
+        Self::capture_file(&self.sink, file_stream::Sink::override_shout, passthrough, capture, f)
+    }
+
+    pub fn capture_stderr<T, F: FnOnce() -> T>(
+        &self,
+        passthrough: bool,
+        capture: bool,
+        f: F,
+    ) -> (Option<BString>, T) {
+        Self::capture_file(&self.err_sink, file_stream::Sink::override_stderr, passthrough, capture, f)
+    }
+
+    fn capture_file<T, F: FnOnce() -> T>(
+        sink: &RefCell<file_stream::Sink>,
+        override_file: for<'a> fn(&'a mut file_stream::Sink, bool, bool) -> file_stream::FileGuard<'a>,
+        passthrough: bool,
+        capture: bool,
+        f: F,
+    ) -> (Option<BString>, T) {
 
-        if let Ok(mut sink) = self.sink.try_borrow_mut() {
+        if let Ok(mut sink) = sink.try_borrow_mut() {
             unsafe {
                 let old_trashedzle = zsh::trashedzle;
                 zsh::trashedzle = 1;
                 sink.clear();
-                let guard = sink.override_shout(passthrough, capture);
+                let guard = override_file(&mut *sink, passthrough, capture);
                 let result = f();
                 drop(guard);
                 zsh::trashedzle = old_trashedzle;
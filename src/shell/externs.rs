@@ -116,6 +116,37 @@ unsafe extern "C" fn handlerfunc(_nam: *mut c_char, argv: *mut *mut c_char, _opt
             }
         },
 
+        Some(b"reload") => {
+            let result = (|| {
+                let ui = GlobalState::get()?;
+                ui.shell_loop(false, ui.reload_lua())??;
+                anyhow::Ok(())
+            })();
+
+            if let Err(e) = result {
+                eprintln!("{e:?}");
+                1
+            } else {
+                0
+            }
+        },
+
+        Some(b"options") => {
+            let result = GlobalState::with(|ui| {
+                for option in ui.try_borrow()?.options.iter() {
+                    println!("{option}");
+                }
+                anyhow::Ok(())
+            });
+
+            if let Err(e) = result.and_then(|r| r) {
+                eprintln!("{e:?}");
+                1
+            } else {
+                0
+            }
+        },
+
         Some(b".invoke-signal-handler") => {
             zsh::signals::invoke_signal_handler_entrypoint(iter.next())
         },
@@ -9,18 +9,14 @@ use std::os::raw::*;
 include!(concat!(env!("OUT_DIR"), "/fopencookie.rs"));
 
 use std::ptr::{NonNull};
-use std::time::{Instant, Duration};
 use bstr::{BString, ByteVec};
 use crate::shell::externs::GlobalState;
 use anyhow::Result;
 
-const MAX_DRAW_DURATION: Duration = Duration::from_millis(50);
-
 pub struct Cookie {
     pub dirty: bool,
     passthrough: bool,
     buffer: Option<BString>,
-    last_draw: Instant,
 }
 
 impl Cookie {
@@ -30,7 +26,6 @@ impl Cookie {
             dirty: false,
             passthrough: false,
             buffer: None,
-            last_draw: Instant::now().checked_sub(MAX_DRAW_DURATION).unwrap(),
         });
         let funcs = cookie_io_functions_t {
             read: None,
@@ -65,7 +60,8 @@ unsafe extern "C" fn cookie_write(cookie: *mut c_void, buf: *const c_char, mut s
             // so there should always be a follow up call
             let draw = if cookie.passthrough && size == 8192 {
                 size -= 1;
-                cookie.last_draw.elapsed() >= MAX_DRAW_DURATION
+                // the frame scheduler decides how often is too often
+                GlobalState::with(|ui| ui.frame_scheduler.is_frame_due()).unwrap_or(true)
             } else {
                 cookie.passthrough
             };
@@ -75,7 +71,6 @@ unsafe extern "C" fn cookie_write(cookie: *mut c_void, buf: *const c_char, mut s
                 buffer.push_str(buf);
             }
             if draw {
-                cookie.last_draw = Instant::now();
                 // okkkkkkkk
                 let result = GlobalState::with(|ui| {
                     ui.try_borrow_mut()?.tui.add_zle_message(buf);
@@ -26,6 +26,8 @@ pub mod history;
 pub mod completion;
 pub mod bin_zle;
 pub mod parser;
+pub mod resolve;
+pub mod expand;
 pub(super) use bindings::*;
 use variables::{Variable};
 pub use meta_string::{MetaStr, MetaString, array::{MetaArray, MetaSlice}};
@@ -373,6 +375,18 @@ pub fn set_error_verbosity(verbosity: ErrorVerbosity) -> ErrorVerbosity {
     }
 }
 
+// zerr prefixes every line with the script name and maybe line number
+pub fn strip_error_prefix(line: &[u8]) -> &[u8] {
+    let Some(colon) = line.find_byte(b':')
+        else { return line };
+    let mut rest = &line[colon + 1 ..];
+    let digits = rest.iter().take_while(|c| c.is_ascii_digit()).count();
+    if digits > 0 && rest.get(digits) == Some(&b':') {
+        rest = &rest[digits + 1 ..];
+    }
+    rest.strip_prefix(b" ").unwrap_or(rest)
+}
+
 pub fn exit(code: i32) {
     unsafe {
         zsh_sys::exit_pending = 1;
@@ -0,0 +1,228 @@
+use std::ffi::CStr;
+use std::os::raw::c_char;
+use std::ptr::null_mut;
+use bstr::BString;
+use serde::Deserialize;
+use anyhow::Result;
+use super::bindings::token;
+use super::MetaStr;
+
+#[derive(Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct ExpandOptions {
+    pub glob: bool,
+    pub params: bool,
+    pub braces: bool,
+    pub tilde: bool,
+    pub history: bool,
+}
+
+impl Default for ExpandOptions {
+    fn default() -> Self {
+        Self {
+            glob: true,
+            params: true,
+            braces: true,
+            tilde: true,
+            history: true,
+        }
+    }
+}
+
+fn is_token(c: u8, tok: token) -> bool {
+    c == tok as u8
+}
+
+// glob qualifiers that run code, e.g. *(e:cmd:) or *(+func)
+fn has_exec_qualifier(group: &[u8]) -> bool {
+    // (#q...) can appear anywhere with extendedglob
+    let group = group.strip_prefix(&[token::Pound as u8, b'q']).unwrap_or(group);
+    group.windows(2).any(|w| {
+        (w[0] == b'e' && !w[1].is_ascii_alphanumeric())
+            || (w[0] == b'+' && (w[1].is_ascii_alphabetic() || w[1] == b'_'))
+    })
+}
+
+// subscripts are evaluated as arithmetic, e.g. $x[i++] or ${x[(i=1)]}
+fn has_arith_side_effects(expr: &[u8]) -> bool {
+    let expr: Vec<u8> = expr.iter().map(|&c| if is_token(c, token::Equals) { b'=' } else { c }).collect();
+    expr.windows(2).any(|w| w == b"++" || w == b"--")
+        || expr.iter().enumerate().any(|(i, &c)| {
+            if c != b'=' || expr.get(i + 1) == Some(&b'=') {
+                return false
+            }
+            // ==, !=, <=, >= are comparisons but <<= and >>= assign
+            match i.checked_sub(1).map(|i| expr[i]) {
+                Some(b'=' | b'!') => false,
+                Some(p @ (b'<' | b'>')) => i >= 2 && expr[i - 2] == p,
+                _ => true,
+            }
+        })
+}
+
+// refuse anything that could run commands or assign variables
+fn check_side_effects(word: &[u8], options: ExpandOptions) -> Result<()> {
+    for (i, &c) in word.iter().enumerate() {
+        let prev = i.checked_sub(1).map(|i| word[i]);
+        let next = word.get(i + 1).copied();
+        let after_dollar = prev.is_some_and(|p| is_token(p, token::String) || is_token(p, token::Qstring));
+
+        if is_token(c, token::Tick) || is_token(c, token::Qtick) {
+            anyhow::bail!("refusing to expand command substitution");
+        }
+        if is_token(c, token::Inparmath) || (is_token(c, token::Inbrack) && after_dollar) {
+            anyhow::bail!("refusing to expand arithmetic");
+        }
+        // e.g. $(( nested inside ${...} may not be tokenized as Inparmath
+        if after_dollar && (c == b'(' || is_token(c, token::Inpar)) && next.is_some_and(|n| n == b'(' || is_token(n, token::Inpar)) {
+            anyhow::bail!("refusing to expand arithmetic");
+        }
+        if (c == b'[' || is_token(c, token::Inbrack)) && !after_dollar {
+            let end = word[i..].iter().position(|&c| c == b']' || is_token(c, token::Outbrack)).map_or(word.len(), |x| x + i);
+            if has_arith_side_effects(&word[i + 1 .. end]) {
+                anyhow::bail!("refusing to expand subscript with side effects");
+            }
+        }
+        // ~[...] runs the zsh_directory_name function
+        if options.tilde && is_token(c, token::Tilde) && next.is_some_and(|n| n == b'[' || is_token(n, token::Inbrack)) {
+            anyhow::bail!("refusing to expand dynamic named directory");
+        }
+        if is_token(c, token::Inpar) {
+            if after_dollar {
+                anyhow::bail!("refusing to expand command substitution");
+            }
+            if prev.is_some_and(|p| is_token(p, token::Inang) || is_token(p, token::OutangProc) || is_token(p, token::Equals)) {
+                anyhow::bail!("refusing to expand process substitution");
+            }
+            if options.glob {
+                let end = word[i..].iter().position(|&c| is_token(c, token::Outpar)).map_or(word.len(), |x| x + i);
+                let group = &word[i + 1 .. end];
+                // qualifiers are a group at the end of the word or (#q...)
+                // anything else, e.g. *.(jpe|png), is just a pattern
+                let is_qualifier = end + 1 >= word.len() || group.starts_with(&[token::Pound as u8, b'q']);
+                if is_qualifier && !group.iter().any(|&c| is_token(c, token::Bar)) && has_exec_qualifier(group) {
+                    anyhow::bail!("refusing to expand glob qualifiers that run code");
+                }
+            }
+        }
+        if is_token(c, token::Inbrace) && after_dollar {
+            let end = word[i..].iter().position(|&c| is_token(c, token::Outbrace)).map_or(word.len(), |x| x + i);
+            let inner = &word[i + 1 .. end];
+            // ${x=y}, ${x:=y}, ${x::=y} all assign
+            if inner.iter().any(|&c| c == b'=' || is_token(c, token::Equals)) {
+                anyhow::bail!("refusing to expand parameter assignment");
+            }
+            // ${(e)x} evaluates the value
+            if next.is_some_and(|n| is_token(n, token::Inpar)) {
+                let flags_end = inner.iter().position(|&c| is_token(c, token::Outpar)).unwrap_or(inner.len());
+                if inner[..flags_end].contains(&b'e') {
+                    anyhow::bail!("refusing to expand parameter with the e flag");
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// turn tokens back into plain characters so they are not expanded
+fn disable_tokens(word: &mut [u8], options: ExpandOptions) {
+    for c in word.iter_mut() {
+        if !options.params && (is_token(*c, token::String) || is_token(*c, token::Qstring)) {
+            *c = b'$';
+        } else if !options.tilde && is_token(*c, token::Tilde) {
+            *c = b'~';
+        } else if !options.tilde && is_token(*c, token::Equals) {
+            *c = b'=';
+        }
+    }
+}
+
+// lex the input into tokenized words on the heap
+unsafe fn lex_words(input: &MetaStr) -> Result<Vec<*mut c_char>> {
+    unsafe {
+        zsh_sys::zcontext_save();
+        zsh_sys::inpush(input.as_ptr().cast_mut(), 0, null_mut());
+        zsh_sys::strinbeg(0);
+        let old_noaliases = zsh_sys::noaliases;
+        let old_lexflags = zsh_sys::lexflags;
+        zsh_sys::noaliases = 1;
+        zsh_sys::lexflags = 0;
+        zsh_sys::incmdpos = 0;
+
+        let mut words = vec![];
+        let result = loop {
+            zsh_sys::zshlex();
+            if zsh_sys::errflag != 0 {
+                break Err(anyhow::anyhow!("parse error"))
+            }
+            match zsh_sys::tok {
+                zsh_sys::lextok_STRING => words.push(zsh_sys::dupstring(zsh_sys::tokstr)),
+                zsh_sys::lextok_NEWLIN => (),
+                zsh_sys::lextok_ENDINPUT => break Ok(words),
+                zsh_sys::lextok_LEXERR => break Err(anyhow::anyhow!("parse error")),
+                _ => break Err(anyhow::anyhow!("only words can be expanded")),
+            }
+        };
+
+        zsh_sys::lexflags = old_lexflags;
+        zsh_sys::noaliases = old_noaliases;
+        zsh_sys::strinend();
+        zsh_sys::inpop();
+        zsh_sys::zcontext_restore();
+        result
+    }
+}
+
+unsafe fn expand_internal(input: &MetaStr, options: ExpandOptions) -> Result<Vec<BString>> {
+    unsafe {
+        let words = lex_words(input)?;
+
+        let list = zsh_sys::newlinklist();
+        // push in reverse so they end up in order
+        for &word in words.iter().rev() {
+            let bytes = std::slice::from_raw_parts_mut(word.cast::<u8>(), CStr::from_ptr(word).count_bytes());
+            check_side_effects(bytes, options)?;
+            disable_tokens(bytes, options);
+            zsh_sys::insertlinknode(list, list.cast(), word.cast());
+        }
+
+        let old_ignorebraces = zsh_sys::opts[zsh_sys::IGNOREBRACES as usize];
+        if !options.braces {
+            zsh_sys::opts[zsh_sys::IGNOREBRACES as usize] = 1;
+        }
+
+        zsh_sys::prefork(list, 0, null_mut());
+        if zsh_sys::errflag == 0 && options.glob {
+            zsh_sys::globlist(list, 0);
+        }
+
+        zsh_sys::opts[zsh_sys::IGNOREBRACES as usize] = old_ignorebraces;
+
+        if zsh_sys::errflag != 0 {
+            anyhow::bail!("expansion failed");
+        }
+
+        Ok(super::linked_list::iter_linklist(list).map(|word| {
+            let word = word.cast::<c_char>();
+            zsh_sys::untokenize(word);
+            MetaStr::from_ptr(word).unmetafy().into_owned()
+        }).collect())
+    }
+}
+
+// expands words like they would be on the command line
+// but without anything that has side effects, e.g. command substitution
+pub fn expand(input: &MetaStr, options: ExpandOptions) -> Result<Vec<BString>> {
+    unsafe {
+        zsh_sys::pushheap();
+        let old_noerrs = super::set_error_verbosity(super::ErrorVerbosity::Normal);
+        zsh_sys::errflag = 0;
+
+        let result = expand_internal(input, options);
+
+        zsh_sys::errflag &= !zsh_sys::errflag_bits_ERRFLAG_ERROR as i32;
+        super::set_error_verbosity(old_noerrs);
+        zsh_sys::popheap();
+        result
+    }
+}
@@ -3,11 +3,13 @@ use std::ptr::NonNull;
 use std::cell::{RefCell};
 use bstr::{BStr, BString, ByteSlice, ByteVec};
 use std::os::raw::{c_char, c_int};
-use serde::{Deserialize};
+use serde::{Deserialize, Serialize};
 use std::ops::Range;
 use std::ptr::null_mut;
 use super::bindings::{token, lextok, CommandStack};
 use super::{MetaStr, MetaString};
+mod commands;
+pub use commands::{SimpleCommand, extract_commands};
 
 fn untokenize(mut c: u8) -> u8 {
     if super::is_token(c) {
@@ -254,6 +256,23 @@ impl Token {
         string
     }
 
+    fn shift(&mut self, offset: usize) {
+        self.range.start += offset;
+        self.range.end += offset;
+        for child in self.children.iter_mut().flatten() {
+            child.shift(offset);
+        }
+    }
+
+    fn contains_heredoc(&self) -> bool {
+        matches!(self.kind,
+            TokenKind::Lextok(lextok::DINANG | lextok::DINANGDASH)
+            | TokenKind::Heredoc(_)
+            | TokenKind::HeredocEnd
+            | TokenKind::Scope(CommandStack::Heredoc | CommandStack::Heredocd)
+        ) || self.children.iter().flatten().any(|c| c.contains_heredoc())
+    }
+
     fn children_end(&self) -> Option<usize> {
         self.children.as_ref().and_then(|n| n.last()).map(|t| t.range.end)
     }
@@ -490,6 +509,126 @@ pub fn parse(mut cmd: BString, options: ParserOptions) -> (bool, Vec<Token>) {
     parse_internal(cmd.as_ref(), options, len)
 }
 
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnostic {
+    pub start: usize,
+    pub finish: usize,
+    pub severity: &'static str,
+    pub message: BString,
+}
+
+// unclosed quote or scope at the end of the input
+fn find_unterminated(tokens: &[Token], len: usize) -> Option<&Token> {
+    let last = tokens.last()?;
+    if last.range.end < len {
+        return None
+    }
+    last.children.as_deref()
+        .and_then(|c| find_unterminated(c, len))
+        .or_else(|| matches!(last.kind, TokenKind::Scope(_)).then_some(last))
+}
+
+pub fn make_diagnostics(cmd: &BStr, complete: bool, tokens: &[Token], messages: Option<BString>) -> Vec<Diagnostic> {
+    let len = cmd.trim_end().len();
+    let message = messages.as_ref()
+        .and_then(|m| m.lines().map(super::strip_error_prefix).find(|l| !l.is_empty()))
+        .map(BString::from);
+
+    let error = tokens.iter().find(|t| matches!(t.kind, TokenKind::SyntaxError));
+    if let Some(error) = error {
+        vec![Diagnostic{
+            start: error.range.start + 1,
+            finish: error.range.end.max(error.range.start + 1),
+            severity: "error",
+            message: message.unwrap_or_else(|| "parse error".into()),
+        }]
+    } else if !complete && len > 0 {
+        // not an error yet, more input may fix it
+        let start = find_unterminated(tokens, len).map_or(len, |t| t.range.start + 1);
+        vec![Diagnostic{
+            start,
+            finish: len,
+            severity: "warning",
+            message: message.unwrap_or_else(|| "incomplete input".into()),
+        }]
+    } else {
+        vec![]
+    }
+}
+
+// runs the real zsh parser to get its error messages
+// these are printed to stderr so the caller should capture it
+pub fn print_syntax_errors(cmd: &BStr) {
+    let metafied = MetaString::from(cmd.to_owned());
+
+    unsafe {
+        let old_noerrs = super::set_error_verbosity(super::ErrorVerbosity::Normal);
+        let old_noaliases = zsh_sys::noaliases;
+        let old_lastval = zsh_sys::lastval;
+        zsh_sys::noaliases = 1;
+        zsh_sys::errflag = 0;
+
+        zsh_sys::parse_string(metafied.as_ptr().cast_mut(), 0);
+
+        zsh_sys::errflag &= !zsh_sys::errflag_bits_ERRFLAG_ERROR as i32;
+        zsh_sys::lastval = old_lastval;
+        zsh_sys::noaliases = old_noaliases;
+        super::set_error_verbosity(old_noerrs);
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ParseTree {
+    pub contents: BString,
+    pub complete: bool,
+    pub tokens: Vec<Token>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl ParseTree {
+    pub fn new(contents: BString, options: ParserOptions) -> Self {
+        let (complete, tokens) = parse(contents.clone(), options);
+        Self{ contents, complete, tokens, diagnostics: vec![] }
+    }
+
+    // reparse after an edit, also returns whether the tokens changed
+    // whole commands before the edit are reused and only the rest is parsed again
+    pub fn update(&self, contents: BString, options: ParserOptions) -> (Self, bool) {
+        if contents == self.contents {
+            return (self.clone(), false)
+        }
+        // heredoc bodies come after the command so everything is tied together
+        if self.tokens.iter().any(|t| t.contains_heredoc()) {
+            return (Self::new(contents, options), true)
+        }
+
+        let edit_start = self.contents.iter().zip(contents.iter()).take_while(|(a, b)| a == b).count();
+
+        // only trailing whitespace changed
+        if self.complete && edit_start == self.contents.len() && contents[edit_start..].trim().is_empty() {
+            return (Self{ contents, complete: true, tokens: self.tokens.clone(), diagnostics: self.diagnostics.clone() }, false)
+        }
+
+        // last top level separator before the edit
+        // not one that ends where the edit starts as the edit may extend it, e.g. & -> &&
+        let boundary = self.tokens.iter()
+            .enumerate()
+            .take_while(|(_, t)| t.range.end < edit_start)
+            .filter(|(_, t)| matches!(t.kind, TokenKind::Lextok(lextok::SEPER | lextok::NEWLIN | lextok::AMPER)))
+            .last();
+        let Some((index, boundary)) = boundary
+            else { return (Self::new(contents, options), true) };
+
+        let offset = boundary.range.end;
+        let (complete, mut rest) = parse(contents[offset..].into(), options);
+        rest.iter_mut().for_each(|t| t.shift(offset));
+
+        let mut tokens = self.tokens[..=index].to_vec();
+        tokens.append(&mut rest);
+        (Self{ contents, complete, tokens, diagnostics: vec![] }, true)
+    }
+}
+
 #[derive(Default)]
 struct ParseState {
     meta: MetaString,
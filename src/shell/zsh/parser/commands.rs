@@ -0,0 +1,401 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use bstr::{BStr, BString, ByteSlice, ByteVec};
+use serde::Serialize;
+use super::{Token, TokenKind, lextok, CommandStack};
+
+// operators that can follow a simple command, longest first
+const CONNECTORS: &[&str] = &["||", "|&", "|", "&&", "&|", "&!", "&", ";;", ";&", ";|", ";", "\n"];
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Word {
+    pub start: usize,
+    pub finish: usize,
+    pub text: BString,
+    pub value: BString,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Assignment {
+    pub start: usize,
+    pub finish: usize,
+    pub name: BString,
+    pub value: BString,
+    pub array: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Redirection {
+    pub start: usize,
+    pub finish: usize,
+    pub fd: u32,
+    pub op: BString,
+    pub target: Option<Word>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SimpleCommand {
+    pub start: usize,
+    pub finish: usize,
+    pub command: Option<Word>,
+    pub argv: Vec<Word>,
+    pub assignments: Vec<Assignment>,
+    pub redirections: Vec<Redirection>,
+    // operator following this command, if any
+    pub connector: Option<&'static str>,
+    // commands in the same pipeline share this
+    pub pipeline: usize,
+    // enclosing constructs, outermost first
+    pub context: Vec<&'static str>,
+}
+
+fn context_name(cs: CommandStack) -> Option<&'static str> {
+    match cs {
+        CommandStack::Subsh => Some("subshell"),
+        CommandStack::Cursh | CommandStack::Brace => Some("brace"),
+        CommandStack::Cmdsubst | CommandStack::Bquote => Some("cmdsubst"),
+        CommandStack::Function => Some("function"),
+        CommandStack::If | CommandStack::Then | CommandStack::Else | CommandStack::Elif | CommandStack::ElifThen => Some("if"),
+        CommandStack::While => Some("while"),
+        CommandStack::Until => Some("until"),
+        CommandStack::For | CommandStack::Foreach => Some("for"),
+        CommandStack::Select => Some("select"),
+        CommandStack::Repeat => Some("repeat"),
+        CommandStack::Case => Some("case"),
+        CommandStack::Always => Some("always"),
+        _ => None,
+    }
+}
+
+// can this token be (part of) a word
+fn is_wordish(token: &Token) -> bool {
+    match token.kind {
+        TokenKind::Lextok(lextok::STRING | lextok::ENVSTRING | lextok::ENVARRAY | lextok::LEXERR | lextok::TYPESET) => true,
+        TokenKind::Scope(
+            CommandStack::Quote
+            | CommandStack::Dquote
+            | CommandStack::Bquote
+            | CommandStack::Cmdsubst
+            | CommandStack::Mathsubst
+            | CommandStack::Braceparam
+            | CommandStack::Array
+        ) => true,
+        TokenKind::Redirect | TokenKind::Token(_) | TokenKind::None => true,
+        TokenKind::Lextok(lextok::DINANG | lextok::DINANGDASH) => true,
+        _ => false,
+    }
+}
+
+fn parse_c_escape(word: &[u8], out: &mut BString) -> usize {
+    // returns how many bytes were consumed after the backslash
+    let Some(&c) = word.first()
+        else { return 0 };
+    let simple = match c {
+        b'n' => Some(b'\n'),
+        b't' => Some(b'\t'),
+        b'r' => Some(b'\r'),
+        b'a' => Some(0x07),
+        b'b' => Some(0x08),
+        b'e' | b'E' => Some(0x1b),
+        b'f' => Some(0x0c),
+        b'v' => Some(0x0b),
+        b'\\' | b'\'' | b'"' => Some(c),
+        _ => None,
+    };
+    if let Some(c) = simple {
+        out.push(c);
+        return 1
+    }
+
+    let (radix, digits, max) = match c {
+        b'x' => (16, &word[1..], 2),
+        b'0'..=b'7' => (8, word, 3),
+        _ => {
+            out.push(b'\\');
+            out.push(c);
+            return 1
+        },
+    };
+    let len = digits.iter().take(max).take_while(|c| (**c as char).is_digit(radix)).count();
+    if len == 0 {
+        out.push(b'\\');
+        out.push(c);
+        return 1
+    }
+    let value = u32::from_str_radix(std::str::from_utf8(&digits[..len]).unwrap(), radix).unwrap();
+    // e.g. \777 does not fit in a byte
+    out.push(value.min(u8::MAX as u32) as u8);
+    len + usize::from(c == b'x')
+}
+
+// remove quoting, does no expansion
+pub fn unquote(word: &[u8]) -> BString {
+    let mut out = BString::default();
+    let mut i = 0;
+    while i < word.len() {
+        match word[i] {
+            b'\\' => {
+                if let Some(&c) = word.get(i + 1) && c != b'\n' {
+                    out.push(c);
+                }
+                i += 2;
+            },
+            b'\'' => {
+                let end = word[i + 1 ..].find_byte(b'\'').map_or(word.len(), |x| x + i + 1);
+                out.push_str(&word[i + 1 .. end]);
+                i = end + 1;
+            },
+            b'$' if word.get(i + 1) == Some(&b'\'') => {
+                i += 2;
+                while i < word.len() && word[i] != b'\'' {
+                    if word[i] == b'\\' {
+                        i += 1 + parse_c_escape(&word[i + 1 ..], &mut out);
+                    } else {
+                        out.push(word[i]);
+                        i += 1;
+                    }
+                }
+                i += 1;
+            },
+            b'"' => {
+                i += 1;
+                while i < word.len() && word[i] != b'"' {
+                    if word[i] == b'\\' && let Some(&c) = word.get(i + 1) && matches!(c, b'$' | b'`' | b'"' | b'\\' | b'\n') {
+                        if c != b'\n' {
+                            out.push(c);
+                        }
+                        i += 2;
+                    } else {
+                        out.push(word[i]);
+                        i += 1;
+                    }
+                }
+                i += 1;
+            },
+            c => {
+                out.push(c);
+                i += 1;
+            },
+        }
+    }
+    out
+}
+
+fn make_word(cmd: &BStr, range: Range<usize>) -> Word {
+    let text = &cmd[range.clone()];
+    Word{
+        start: range.start + 1,
+        finish: range.end,
+        text: text.into(),
+        value: unquote(text),
+    }
+}
+
+fn make_redirection(cmd: &BStr, op: &Token, target: Option<Range<usize>>) -> Redirection {
+    let op_text = &cmd[op.range.clone()];
+    let digits = op_text.iter().take_while(|c| c.is_ascii_digit()).count();
+    let fd = if digits > 0 {
+        op_text[..digits].to_str().unwrap().parse().unwrap_or(0)
+    } else if op_text.starts_with(b"<") {
+        0
+    } else {
+        1
+    };
+
+    Redirection{
+        start: op.range.start + 1,
+        finish: target.as_ref().map_or(op.range.end, |t| t.end),
+        fd,
+        op: op_text[digits..].into(),
+        target: target.map(|t| make_word(cmd, t)),
+    }
+}
+
+fn build_command(cmd: &BStr, token: &Token, context: &[&'static str]) -> Option<SimpleCommand> {
+    let children = token.children.as_deref().unwrap_or_default();
+    let mut argv = vec![];
+    let mut assignments = vec![];
+    let mut redirections = vec![];
+
+    let mut i = 0;
+    while i < children.len() {
+        let first = &children[i];
+        if !is_wordish(first) {
+            i += 1;
+            continue
+        }
+
+        // array assignments have spaces in them, so take everything up to the closing paren
+        if matches!(first.kind, TokenKind::Lextok(lextok::ENVARRAY)) && argv.is_empty() {
+            let mut end = first.range.end;
+            i += 1;
+            while let Some(child) = children.get(i) {
+                end = child.range.end;
+                i += 1;
+                if matches!(child.kind, TokenKind::Lextok(lextok::OUTPAR)) {
+                    break
+                }
+            }
+            let text = &cmd[first.range.start .. end];
+            let (name, value) = text.split_once_str(b"=").unwrap_or((text, b""));
+            assignments.push(Assignment{
+                start: first.range.start + 1,
+                finish: end,
+                name: name.into(),
+                value: value.into(),
+                array: true,
+            });
+            continue
+        }
+
+        // a word is a run of tokens without any space between them
+        let mut end = first.range.end;
+        let mut j = i + 1;
+        while let Some(child) = children.get(j) && child.range.start == end && is_wordish(child) {
+            end = child.range.end;
+            j += 1;
+        }
+        let range = first.range.start .. end;
+
+        match first.kind {
+            TokenKind::Redirect => {
+                let op = first.children.as_deref().and_then(|c| c.first()).unwrap_or(first);
+                let target = (op.range.end < end).then(|| {
+                    // skip blanks between the op and the target
+                    let start = cmd[op.range.end .. end].iter().position(|c| !c.is_ascii_whitespace()).map_or(end, |x| x + op.range.end);
+                    start .. end
+                });
+                redirections.push(make_redirection(cmd, op, target));
+            },
+            // heredocs are not merged into a redirect, the marker is the next word
+            TokenKind::Lextok(lextok::DINANG | lextok::DINANGDASH) => {
+                let target = if first.range.end < end {
+                    Some(first.range.end .. end)
+                } else if let Some(next) = children.get(j) && is_wordish(next) {
+                    let mut end = next.range.end;
+                    j += 1;
+                    while let Some(child) = children.get(j) && child.range.start == end && is_wordish(child) {
+                        end = child.range.end;
+                        j += 1;
+                    }
+                    Some(next.range.start .. end)
+                } else {
+                    None
+                };
+                redirections.push(make_redirection(cmd, first, target));
+            },
+            TokenKind::Lextok(lextok::ENVSTRING) if argv.is_empty() => {
+                let text = &cmd[range.clone()];
+                let (name, value) = text.split_once_str(b"=").unwrap_or((text, b""));
+                assignments.push(Assignment{
+                    start: range.start + 1,
+                    finish: range.end,
+                    name: name.into(),
+                    value: unquote(value),
+                    array: false,
+                });
+            },
+            _ => argv.push(make_word(cmd, range)),
+        }
+        i = j;
+    }
+
+    if argv.is_empty() && assignments.is_empty() && redirections.is_empty() {
+        // probably just a compound command
+        return None
+    }
+
+    // what comes after the command
+    let rest = &cmd[token.range.end ..];
+    let rest = &rest[rest.iter().position(|c| !matches!(c, b' ' | b'\t')).unwrap_or(rest.len()) ..];
+    let connector = CONNECTORS.iter().find(|c| rest.starts_with(c.as_bytes())).copied();
+
+    Some(SimpleCommand{
+        start: token.range.start + 1,
+        finish: token.range.end,
+        command: argv.first().cloned(),
+        argv,
+        assignments,
+        redirections,
+        connector,
+        pipeline: 0,
+        context: context.to_owned(),
+    })
+}
+
+// where a pipeline ends, and in which context
+struct Boundary {
+    pos: usize,
+    context: Vec<&'static str>,
+}
+
+fn walk(
+    cmd: &BStr,
+    tokens: &[Token],
+    context: &mut Vec<&'static str>,
+    commands: &mut Vec<SimpleCommand>,
+    boundaries: &mut Vec<Boundary>,
+) {
+    for token in tokens {
+        match token.kind {
+            TokenKind::Command => {
+                commands.extend(build_command(cmd, token, context));
+            },
+            TokenKind::Lextok(
+                lextok::SEPER
+                | lextok::NEWLIN
+                | lextok::SEMI
+                | lextok::DSEMI
+                | lextok::SEMIAMP
+                | lextok::SEMIBAR
+                | lextok::AMPER
+                | lextok::AMPERBANG
+                | lextok::DAMPER
+                | lextok::DBAR
+            ) => {
+                boundaries.push(Boundary{ pos: token.range.start, context: context.clone() });
+            },
+            // heredoc bodies are just text
+            TokenKind::Heredoc(_) => continue,
+            _ => (),
+        }
+
+        let name = if let TokenKind::Scope(cs) = token.kind { context_name(cs) } else { None };
+        context.extend(name);
+        if let Some(children) = &token.children {
+            walk(cmd, children, context, commands, boundaries);
+        }
+        if name.is_some() {
+            context.pop();
+        }
+    }
+}
+
+pub fn extract_commands(cmd: &BStr, tokens: &[Token]) -> Vec<SimpleCommand> {
+    let mut commands = vec![];
+    let mut boundaries = vec![];
+    walk(cmd, tokens, &mut vec![], &mut commands, &mut boundaries);
+    commands.sort_by_key(|c| c.start);
+    boundaries.sort_by_key(|b| b.pos);
+
+    // the pipe is only connected to the next command in the same context
+    let mut counter = 0;
+    let mut piped: HashMap<Vec<&'static str>, usize> = HashMap::new();
+    let mut boundaries = boundaries.into_iter().peekable();
+    for command in &mut commands {
+        // e.g. the ; in a | (b); c ends the pipeline even though (b) is not a simple command
+        // (start is 1-based)
+        while let Some(boundary) = boundaries.next_if(|b| b.pos < command.start - 1) {
+            piped.remove(&boundary.context);
+        }
+        command.pipeline = piped.remove(&command.context).unwrap_or_else(|| {
+            counter += 1;
+            counter
+        });
+        if matches!(command.connector, Some("|" | "|&")) {
+            piped.insert(command.context.clone(), command.pipeline);
+        }
+    }
+
+    commands
+}
@@ -0,0 +1,133 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::ptr::{NonNull, null_mut};
+use bstr::{BString, ByteSlice};
+use serde::Serialize;
+use crate::meta_str;
+use super::{MetaStr, MetaString, variables::Variable};
+
+// commands that were not found in $path
+// findcmd scans all of $path for these which is too slow to do on every keypress
+#[derive(Default)]
+struct NotFound {
+    path: BString,
+    // rehash or running a new command changes the size of the hash table
+    hashed: i32,
+    names: HashSet<BString>,
+}
+
+thread_local! {
+    static NOT_FOUND: RefCell<NotFound> = RefCell::default();
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ResolvedCommand {
+    // same names as whence -w
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub path: Option<BString>,
+    pub definition: Option<BString>,
+}
+
+impl ResolvedCommand {
+    fn new(kind: &'static str) -> Self {
+        Self{ kind, ..Self::default() }
+    }
+}
+
+unsafe fn get_node(table: zsh_sys::HashTable, name: &MetaStr) -> Option<NonNull<zsh_sys::hashnode>> {
+    unsafe {
+        let getnode = table.as_ref()?.getnode?;
+        // getnode skips disabled nodes
+        NonNull::new(getnode(table, name.as_ptr()))
+    }
+}
+
+unsafe fn get_str(ptr: *const std::os::raw::c_char) -> Option<BString> {
+    if ptr.is_null() {
+        None
+    } else {
+        Some(unsafe{ MetaStr::from_ptr(ptr) }.unmetafy().into_owned())
+    }
+}
+
+// resolves in the same order as whence
+pub fn resolve_command(name: &MetaStr) -> ResolvedCommand {
+    unsafe {
+        if super::isset(zsh_sys::ALIASESOPT as _)
+            && let Some(node) = get_node(zsh_sys::aliastab, name)
+        {
+            let alias: NonNull<zsh_sys::alias> = node.cast();
+            return ResolvedCommand{
+                definition: get_str(alias.as_ref().text),
+                ..ResolvedCommand::new("alias")
+            }
+        }
+
+        if get_node(zsh_sys::reswdtab, name).is_some() {
+            return ResolvedCommand::new("reserved")
+        }
+
+        if let Some(node) = get_node(zsh_sys::shfunctab, name) {
+            let func: NonNull<zsh_sys::shfunc> = node.cast();
+            let func = func.as_ref();
+            // autoloaded functions have no body until they are first run
+            let definition = if func.node.flags & zsh_sys::PM_UNDEFINED as i32 != 0 || func.funcdef.is_null() {
+                None
+            } else {
+                let ptr = zsh_sys::getpermtext(func.funcdef, null_mut(), 1);
+                Some(MetaString::from_raw(ptr).unmetafy())
+            };
+            return ResolvedCommand{
+                path: get_str(func.filename),
+                definition,
+                ..ResolvedCommand::new("function")
+            }
+        }
+
+        if get_node(zsh_sys::builtintab, name).is_some() {
+            return ResolvedCommand::new("builtin")
+        }
+
+        // explicitly hashed with hash foo=/path
+        let hashed = get_node(zsh_sys::cmdnamtab, name).is_some_and(|node| node.as_ref().flags & zsh_sys::HASHED as i32 != 0);
+        let path = find_command(name);
+        match (path, hashed) {
+            (Some(path), true) => ResolvedCommand{ path: Some(path), ..ResolvedCommand::new("hashed") },
+            (Some(path), false) => ResolvedCommand{ path: Some(path), ..ResolvedCommand::new("command") },
+            (None, _) => ResolvedCommand::new("none"),
+        }
+    }
+}
+
+unsafe fn find_command(name: &MetaStr) -> Option<BString> {
+    let path = Variable::get(meta_str!(c"PATH")).map(|mut var| var.as_bytes()).unwrap_or_default();
+    let hashed = unsafe{ zsh_sys::cmdnamtab.as_ref() }.map_or(0, |table| table.ct);
+
+    let not_found = NOT_FOUND.with_borrow_mut(|not_found| {
+        if not_found.path != path || not_found.hashed != hashed {
+            not_found.path = path;
+            not_found.hashed = hashed;
+            not_found.names.clear();
+        }
+        not_found.names.contains(name.to_bytes().as_bstr())
+    });
+    if not_found {
+        return None
+    }
+
+    // this searches $path if it is not in the hash table yet
+    // the path is copied onto the heap (without docopy it just gives back the name)
+    // so use a heap of our own which gets freed once it has been copied out
+    let docopy = 1;
+    let path = unsafe {
+        zsh_sys::pushheap();
+        let path = get_str(zsh_sys::findcmd(name.as_ptr().cast_mut(), docopy, 0));
+        zsh_sys::popheap();
+        path
+    };
+    if path.is_none() {
+        NOT_FOUND.with_borrow_mut(|not_found| not_found.names.insert(name.to_bytes().into()));
+    }
+    path
+}
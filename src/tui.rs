@@ -55,6 +55,18 @@ impl crossterm::Command for MoveDown {
     }
 }
 
+// DECSTBM, None resets it to the whole screen
+pub struct SetScrollRegion(pub Option<(u16, u16)>);
+impl crossterm::Command for SetScrollRegion {
+    fn write_ansi(&self, f: &mut impl std::fmt::Write) -> std::fmt::Result {
+        if let Some((start, end)) = self.0 {
+            write!(f, "\x1b[{};{}r", start + 1, end)
+        } else {
+            f.write_str("\x1b[r")
+        }
+    }
+}
+
 pub fn allocate_height<W: Write>(stdout: &mut W, height: u16) -> std::io::Result<()> {
     for _ in 0 .. height {
         // vertical tab, this doesn't change x
@@ -69,6 +81,13 @@ fn cell_is_empty(cell: &Cell) -> bool {
     cell.text() == " " && matches!(cell.style.bg, None | Some(Color::Reset)) && !active.intersects(Modifier::REVERSED | Modifier::CROSSED_OUT) && matches!(cell.style.underline, None | Some(Underline::None))
 }
 
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DrawStats {
+    // bytes written out to the terminal
+    pub last_frame_bytes: usize,
+    pub total_bytes: usize,
+}
+
 #[derive(Default)]
 pub struct Tui {
     pub nodes: layout::Nodes,
@@ -80,6 +99,9 @@ pub struct Tui {
     zle_msg: Option<layout::NodeId>,
     render_callbacks_counter: usize,
     render_callbacks: HashMap<usize, LuaFunction>,
+    // rows [start, end) that scroll, if the pinned status bars need one
+    scroll_region: Option<(u32, u32)>,
+    pub stats: DrawStats,
 }
 
 impl Tui {
@@ -139,19 +161,60 @@ impl Tui {
         })
     }
 
-    pub fn get_status_bar_geometry(&self, status_bar: &status_bar::StatusBar) -> Option<rect::Rect> {
-        let size = self.get_size();
-        let height = status_bar.get_height();
-        if height == 0 {
-            None
-        } else {
-            Some(rect::Rect{
-                x: 0,
-                y: size.1.saturating_sub(height) + self.top_y as u16,
-                width: size.0,
-                height,
-            })
+    pub fn get_status_bar_geometry(&self, status_bar: &status_bar::StatusBar, name: &str) -> Option<rect::Rect> {
+        status_bar.get_geometry(name, self.get_size(), self.top_y as u16)
+    }
+
+    // these live outside the scroll region so need redrawing whenever the screen is cleared
+    pub fn draw_pinned_status_bars<W: Write>(&self, writer: &mut W, status_bar: &status_bar::StatusBar) -> std::io::Result<()> {
+        status_bar.render_pinned(&self.nodes, writer, self.get_size())
+    }
+
+    pub fn reset_scroll_region<W: Write>(&mut self, writer: &mut W) -> std::io::Result<()> {
+        if self.scroll_region.take().is_some() {
+            queue!(writer, cursor::SavePosition, SetScrollRegion(None), cursor::RestorePosition)?;
         }
+        Ok(())
+    }
+
+    // keep the pinned status bars out of the way of everything else
+    fn set_scroll_region<W: Write>(
+        &mut self,
+        writer: &mut W,
+        (x, y): (u16, u16),
+        region: Option<(u32, u32)>,
+        (ui_height, height): (u32, u32),
+    ) -> std::io::Result<()> {
+        if let Some((start, end)) = self.scroll_region {
+            // clear the old status bars
+            for row in (0 .. start).chain(end .. height) {
+                queue!(writer, cursor::MoveTo(0, row as _), Clear(ClearType::CurrentLine))?;
+            }
+        }
+
+        // let the whole screen scroll while we make room
+        queue!(writer, SetScrollRegion(None))?;
+
+        if let Some((start, end)) = region {
+            let bottom = self.top_y + ui_height.max(1);
+            if bottom > end {
+                // the ui would be hidden by the bottom status bars
+                let n = (bottom - end).min(self.top_y);
+                queue!(writer, cursor::MoveTo(0, height.saturating_sub(1) as _))?;
+                for _ in 0 .. n {
+                    queue!(writer, crossterm::style::Print("\n"))?;
+                }
+                self.top_y -= n;
+            }
+            // anything under the top status bars gets drawn over
+            self.top_y = self.top_y.max(start);
+            queue!(writer, SetScrollRegion(Some((start as _, end as _))))?;
+        }
+
+        // setting the scroll region moves the cursor so put it back
+        queue!(writer, cursor::MoveTo(x, self.top_y as u16 + y))?;
+        self.scroll_region = region;
+        Ok(())
     }
 
     pub fn remove(&mut self, id: layout::NodeId) -> Option<layout::Node> {
@@ -187,6 +250,13 @@ impl Tui {
         self.dirty = true;
     }
 
+    // forget everything that was set up from lua
+    pub fn clear_lua_state(&mut self) {
+        self.nodes.clear_detached();
+        self.clear_all();
+        self.render_callbacks.clear();
+    }
+
     pub fn clear_non_persistent(&mut self) {
         self.clear_error();
         self.clear_zle();
@@ -236,7 +306,7 @@ impl Tui {
         top_y: Option<u32>,
         mut cmdline: command_line::CommandLine<'_>,
         status_bar: &mut status_bar::StatusBar,
-        clear: bool,
+        mut clear: bool,
     ) -> Result<Vec<usize>> {
 
         let mut resized_ids = vec![];
@@ -246,12 +316,21 @@ impl Tui {
             return Ok(resized_ids)
         }
 
+        let mut counter = drawer::ByteCounter::new(writer);
+        let writer = &mut counter;
+
         if clear {
             self.reset();
             cmdline.hard_reset();
             status_bar.reset();
         }
 
+        if self.dirty {
+            // the prompt etc may be made of messages
+            cmdline.set_layouts_dirty();
+            status_bar.dirty |= status_bar.has_layouts();
+        }
+
         // resize buffers
         let area = rect::Rect{x: 0, y: 0, width: width as _, height: height as _};
         self.buffer.resize(area);
@@ -259,27 +338,57 @@ impl Tui {
         // old heights
         let mut old_cmdline_height = cmdline.get_height();
         let mut old_widgets_height = self.nodes.get_height() as usize;
-        let mut old_status_bar_height = status_bar.inner.as_ref().map_or(0, |w| w.line_count) as usize;
+        let mut old_bottom_bar_height = status_bar.get_height(status_bar::Placement::Bottom) as usize;
         if clear {
             old_cmdline_height = 0;
             old_widgets_height = 0;
-            old_status_bar_height = 0;
+            old_bottom_bar_height = 0;
         }
 
-        let old_height = old_cmdline_height + old_widgets_height + old_status_bar_height;
+        let mut old_height = old_cmdline_height + old_widgets_height + old_bottom_bar_height;
 
         // refresh the widgets etc
         if status_bar.dirty {
-            status_bar.refresh(width as _);
+            status_bar.refresh(&self.nodes, width as _);
         }
+
+        // the pinned status bars take rows away from everything else
+        let top_bar_height = status_bar.get_height(status_bar::Placement::PinnedTop) as u32;
+        let bottom_bar_height = status_bar.get_height(status_bar::Placement::PinnedBottom) as u32;
+        let max_height = self.max_height.saturating_sub(top_bar_height + bottom_bar_height);
+        let scroll_region = (top_bar_height > 0 || bottom_bar_height > 0)
+            .then(|| (top_bar_height, height.saturating_sub(bottom_bar_height)));
+        let scroll_end = scroll_region.map_or(height, |region| region.1);
+
+        // full screen programs may have reset it
+        let redraw_pinned = scroll_region != self.scroll_region || (clear && scroll_region.is_some());
+        let ui_height = old_height;
+        if redraw_pinned && !clear {
+            // the ui may get moved around, so draw it all again
+            clear = true;
+            self.reset();
+            cmdline.hard_reset();
+            status_bar.dirty = true;
+            old_cmdline_height = 0;
+            old_height = 0;
+        }
+
+        // the inline status bars go above the prompt
+        let inline_bar_height = status_bar.get_height(status_bar::Placement::Inline);
+        if cmdline.set_y_offset(inline_bar_height) {
+            cmdline.reset();
+        }
+        // and the unpinned bottom ones go at the bottom of the terminal
+        let new_bottom_bar_height = status_bar.get_height(status_bar::Placement::Bottom) as usize;
+
         if cmdline.is_dirty() {
-            cmdline.refresh(width as _, self.max_height.saturating_sub(status_bar.get_height() as _) as _);
+            cmdline.refresh(&self.nodes, width as _, max_height.saturating_sub((inline_bar_height as usize + new_bottom_bar_height) as _) as _);
         }
         if self.dirty {
             // the nodes are the main part of the ui that can be resized to fit on the screen
             // even if there is more space we could get by scrolling, we should avoid it because it is jarring
             // so this is the only one that cares about the height
-            let max_height = self.max_height.saturating_sub(status_bar.get_height() as u32 + cmdline.get_height() as u32);
+            let max_height = max_height.saturating_sub((cmdline.get_height() + new_bottom_bar_height) as u32);
             resized_ids = self.nodes.refresh(
                 width as _,
                 Some(max_height as _),
@@ -289,8 +398,7 @@ impl Tui {
         // new heights
         let new_cmdline_height = cmdline.get_height();
         let new_widgets_height = self.nodes.get_height() as usize;
-        let new_status_bar_height = status_bar.get_height() as usize;
-        let new_height = (new_cmdline_height + new_widgets_height + new_status_bar_height).min(self.max_height as _);
+        let new_height = (new_cmdline_height + new_widgets_height + new_bottom_bar_height).min(max_height as _);
 
         // render callbacks
         if new_widgets_height > 0 && !self.render_callbacks.is_empty() {
@@ -328,8 +436,16 @@ impl Tui {
             });
         }
 
+        queue!(writer, crossterm::terminal::BeginSynchronizedUpdate)?;
+
+        if let Some(top_y) = top_y {
+            self.top_y = top_y;
+        }
+        if redraw_pinned {
+            self.set_scroll_region(writer, cmdline.cursor_coord, scroll_region, (ui_height as _, height))?;
+        }
+
         let mut drawer = drawer::Drawer::new(&mut self.buffer, writer, cmdline.cursor_coord);
-        queue!(drawer.writer, crossterm::terminal::BeginSynchronizedUpdate)?;
         if clear {
             queue!(
                 drawer.writer,
@@ -345,25 +461,27 @@ impl Tui {
             drawer.allocate_height(new_height as u16 - 1)?;
         }
 
-        if let Some(top_y) = top_y {
-            self.top_y = top_y;
-        }
-        if self.top_y + new_height as u32 > height {
+        if self.top_y + new_height as u32 > scroll_end {
             // page has scrolled
-            self.top_y = height - new_height as u32;
-            // invalidate the status bar if the page has scrolled as it needs to stick to the bottom
+            self.top_y = scroll_end - new_height as u32;
+            // invalidate the bottom status bars if the page has scrolled as they need to stick to the bottom
             status_bar.dirty = true;
         }
-        let status_bar_y = area.height.saturating_sub(self.top_y as u16 + new_status_bar_height as u16);
+        // this is relative to the top of the drawing area
+        let scroll_end_y = scroll_end.saturating_sub(self.top_y) as u16;
+        let bottom_bar_y = scroll_end_y.saturating_sub(new_bottom_bar_height as u16);
 
         // move back to top of drawing area
         drawer.move_to((0, 0));
+        if inline_bar_height > 0 && (clear || status_bar.dirty) {
+            status_bar.render_unpinned(status_bar::Placement::Inline, &self.nodes, &mut drawer)?;
+        }
         // draw cmdline
-        cmdline.render(&mut drawer, clear)?;
+        cmdline.render(&self.nodes, &mut drawer, clear)?;
 
         // redraw the widgets
         // if cmdline height has changed then the widgets get repositioned
-        if (clear || self.dirty || old_cmdline_height != new_cmdline_height)
+        if (clear || self.dirty || old_cmdline_height != new_cmdline_height || old_bottom_bar_height != new_bottom_bar_height)
             && drawer.try_move_to(cmdline.draw_end_pos)
         {
             if new_widgets_height > 0 {
@@ -372,9 +490,9 @@ impl Tui {
                 self.nodes.render(&mut drawer, false)?;
             }
 
-            if new_status_bar_height > 0 {
-                // clear everything from here to the start of status bar
-                for _ in drawer.get_pos().1 + 1 .. status_bar_y {
+            if new_bottom_bar_height > 0 || bottom_bar_height > 0 {
+                // clear everything from here to the bottom status bars, but not them
+                for _ in drawer.get_pos().1 + 1 .. bottom_bar_y {
                     drawer.goto_newline(None)?;
                 }
                 drawer.clear_to_end_of_line(None, false)?;
@@ -383,14 +501,19 @@ impl Tui {
             }
         }
 
-        // redraw status bar
-        if new_status_bar_height > 0
+        // redraw the bottom status bars
+        if new_bottom_bar_height > 0
             && (clear || status_bar.dirty)
-            && status_bar.is_visible()
-            && new_height >= new_cmdline_height + new_status_bar_height
-            && drawer.try_move_to((0, status_bar_y))
+            && new_height >= new_cmdline_height + new_bottom_bar_height
+            && drawer.try_move_to((0, bottom_bar_y))
         {
-            status_bar.render(&mut drawer)?;
+            status_bar.render_unpinned(status_bar::Placement::Bottom, &self.nodes, &mut drawer)?;
+        }
+
+        // redraw the pinned status bars
+        if (top_bar_height > 0 || bottom_bar_height > 0) && (redraw_pinned || status_bar.dirty) {
+            drawer.flush()?;
+            status_bar.render_pinned(&self.nodes, drawer.writer, (width as _, height as _))?;
         }
 
         // go back to the cursor
@@ -401,6 +524,8 @@ impl Tui {
         drawer.reset_colours()?;
         execute!(writer, crossterm::terminal::EndSynchronizedUpdate)?;
 
+        self.stats.last_frame_bytes = counter.count;
+        self.stats.total_bytes += counter.count;
         self.dirty = false;
         cmdline.set_is_dirty(false);
         status_bar.dirty = false;
@@ -3,6 +3,7 @@ use super::text::{Highlight, TextRenderer, Renderer, NoRendererCallback};
 use bstr::{BString, BStr};
 use std::io::{Write};
 use crate::tui::{Drawer, Canvas};
+use crate::tui::layout::{Nodes, NodeId};
 use crate::ui::buffer::Buffer;
 use crate::shell::{Shell, MetaStr};
 use crate::meta_str;
@@ -27,6 +28,18 @@ pub struct ShellVars {
 pub enum PromptMode {
     ShellVars(ShellVars),
     Custom{widget: super::widget::Widget},
+    // a node detached from the message tree
+    Layout{id: NodeId},
+}
+
+impl PromptMode {
+    pub fn get_layout_id(&self) -> Option<NodeId> {
+        if let Self::Layout{id} = self {
+            Some(*id)
+        } else {
+            None
+        }
+    }
 }
 
 impl Default for PromptMode {
@@ -39,6 +52,7 @@ impl Default for PromptMode {
 pub enum RightPromptMode {
     ShellVars(ShellVarPrompt),
     Custom{widget: super::widget::Widget, auto_disappear: bool},
+    Layout{id: NodeId, auto_disappear: bool},
     None,
 }
 
@@ -65,6 +79,8 @@ pub struct CommandLineState {
 
     prompt_size: (usize, usize),
     rprompt_size: (usize, usize),
+    // room left above the prompt, e.g. for status bars
+    y_offset: u16,
 }
 
 impl CommandLineState {
@@ -87,6 +103,23 @@ impl CommandLineState {
         self.prompt_dirty || self.rprompt_dirty || self.predisplay_dirty || self.postdisplay_dirty
     }
 
+    // returns whether it changed
+    pub fn set_y_offset(&mut self, y_offset: u16) -> bool {
+        let changed = self.y_offset != y_offset;
+        self.y_offset = y_offset;
+        changed
+    }
+
+    // layouts may contain messages which have changed
+    pub fn set_layouts_dirty(&mut self) {
+        if matches!(self.prompt_mode, PromptMode::Layout{..}) {
+            self.prompt_dirty = true;
+        }
+        if matches!(self.rprompt_mode, RightPromptMode::Layout{..}) {
+            self.rprompt_dirty = true;
+        }
+    }
+
     pub fn y_offset_to_end(&self) -> u16 {
         self.draw_end_pos.1 - self.cursor_coord.1
     }
@@ -180,11 +213,16 @@ impl CommandLine<'_> {
         self.draw_end_pos = (0, 0);
     }
 
-    pub fn refresh(&mut self, width: usize, height: usize) {
+    pub fn refresh(&mut self, nodes: &Nodes, width: usize, height: usize) {
         let prompt_size = match &self.prompt_mode {
             _ if !self.prompt_dirty => self.prompt_size,
             PromptMode::ShellVars(vars) => vars.prompt.size,
             PromptMode::Custom{widget} => widget.inner.get_size(width, 0, widget.cursor_space_hl.iter()),
+            // the buffer goes after the last row, which may be narrower than the rest
+            PromptMode::Layout{id} => match nodes.refresh_detached(*id, width as _, None) {
+                Some((_, height)) if height > 0 => (nodes.get_end_column(*id) as _, height as _),
+                _ => (0, 0),
+            },
         };
 
         let mut rprompt_size = match &self.rprompt_mode {
@@ -196,6 +234,10 @@ impl CommandLine<'_> {
                 let size = widget.inner.get_size(width, 0, widget.cursor_space_hl.iter());
                 (size.0 + 1, size.1)
             },
+            RightPromptMode::Layout{id, ..} => match nodes.refresh_detached(*id, width as _, Some(1)) {
+                Some((width, height)) if width > 0 && height > 0 => (width as usize + 1, height as _),
+                _ => (0, 0),
+            },
         };
 
         if (self.buffer.dirty || self.rprompt_size != rprompt_size) && self.rprompt_mode.can_disappear() {
@@ -221,12 +263,12 @@ impl CommandLine<'_> {
             self.max_buffer_height_value = self.max_buffer_height_metric.resolve(Some(height as _), 1);
             let y = (buf_size.1 + self.prompt_size.1).saturating_sub(2).min(self.max_buffer_height_value.saturating_sub(1) as _);
 
-            self.draw_end_pos = (buf_size.0 as _, y as _);
+            self.draw_end_pos = (buf_size.0 as _, y as u16 + self.y_offset);
             self.buffer.dirty = true;
         }
     }
 
-    pub fn render<W :Write, C: Canvas>(&mut self, drawer: &mut Drawer<W, C>, dirty: bool) -> std::io::Result<()> {
+    pub fn render<W :Write, C: Canvas>(&mut self, nodes: &Nodes, drawer: &mut Drawer<W, C>, dirty: bool) -> std::io::Result<()> {
 
         let mut prompt_end = (self.prompt_size.0 as u16, self.prompt_size.1 as u16);
         if prompt_end.0 >= drawer.term_width() {
@@ -234,10 +276,12 @@ impl CommandLine<'_> {
         } else {
             prompt_end.1 = prompt_end.1.saturating_sub(1);
         }
+        prompt_end.1 += self.y_offset;
 
         // redraw the prompt
         if dirty || self.prompt_dirty {
             let term_width = drawer.term_width() as usize;
+            drawer.move_to((0, self.y_offset));
 
             match &self.prompt_mode {
                 PromptMode::ShellVars(vars) => {
@@ -254,6 +298,9 @@ impl CommandLine<'_> {
                         |parano| widget.inner.highlights.get_for_parano(parano).iter(),
                     ).render(drawer, false, false, NoRendererCallback::None)?;
                 }
+                PromptMode::Layout{id} => {
+                    nodes.render_detached(*id, drawer, false)?;
+                }
             }
         }
 
@@ -284,6 +331,7 @@ impl CommandLine<'_> {
                 predisplay,
                 postdisplay,
                 &self.rprompt_mode,
+                nodes,
                 self.rprompt_size,
                 dirty || self.rprompt_dirty,
             )?;
@@ -297,11 +345,20 @@ impl CommandLine<'_> {
 impl RightPromptMode {
 
     fn can_disappear(&self) -> bool {
-        !matches!(self, Self::Custom{auto_disappear: false, ..})
+        !matches!(self, Self::Custom{auto_disappear: false, ..} | Self::Layout{auto_disappear: false, ..})
+    }
+
+    pub fn get_layout_id(&self) -> Option<NodeId> {
+        if let Self::Layout{id, ..} = self {
+            Some(*id)
+        } else {
+            None
+        }
     }
 
     pub fn render<W :Write, C: Canvas>(
         &self,
+        nodes: &Nodes,
         drawer: &mut Drawer<W, C>,
         size: (usize, usize),
         dirty: bool,
@@ -333,6 +390,9 @@ impl RightPromptMode {
                             |parano| widget.inner.highlights.get_for_parano(parano).iter(),
                         ).render(drawer, false, false, NoRendererCallback::None)?;
                     }
+                    Self::Layout{id, ..} => {
+                        nodes.render_detached(*id, drawer, false)?;
+                    }
                 }
             }
         } else {
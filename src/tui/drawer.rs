@@ -1,4 +1,5 @@
 use std::rc::Rc;
+use std::collections::BTreeMap;
 use unicode_width::UnicodeWidthStr;
 use std::io::{Result, Write};
 use crossterm::{
@@ -76,9 +77,44 @@ impl Canvas for DummyCanvas {
     }
 }
 
+// reprinting this many cells is cheaper than moving the cursor over them
+const MAX_REPRINT_CELLS: u16 = 3;
+// roughly how many bytes a cursor movement takes
+const MOVE_COST: usize = 4;
+// \x1b[K
+const ERASE_COST: usize = 3;
+
+// counts what goes out to the terminal
+pub struct ByteCounter<'a, W> {
+    inner: &'a mut W,
+    pub count: usize,
+}
+
+impl<'a, W> ByteCounter<'a, W> {
+    pub fn new(inner: &'a mut W) -> Self {
+        Self{ inner, count: 0 }
+    }
+}
+
+impl<W: Write> Write for ByteCounter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
+
 pub struct Drawer<'a, 'b, W, C> {
+    // what is currently on the terminal
     canvas: &'a mut C,
     pub writer: &'b mut W,
+    // cells drawn since the last flush, by row
+    // these get diffed against the canvas so only the changes are written out
+    back: BTreeMap<u16, Vec<Option<Cell>>>,
     real_pos: (u16, u16),
     pos: (u16, u16),
     fg: Color,
@@ -94,6 +130,7 @@ impl<'a, 'b, W, C> Drawer<'a, 'b, W, C> {
         Self {
             canvas,
             writer,
+            back: BTreeMap::new(),
             real_pos: pos,
             pos,
             fg: Color::Reset,
@@ -145,6 +182,11 @@ impl<W, C: Canvas> Drawer<'_, '_, W, C> {
 
 impl<W: Write, C: Canvas> Drawer<'_, '_, W, C> {
     pub fn move_to_pos(&mut self, pos: (u16, u16), force_wrap: bool) -> Result<()> {
+        self.flush()?;
+        self.move_cursor(pos, force_wrap)
+    }
+
+    fn move_cursor(&mut self, pos: (u16, u16), force_wrap: bool) -> Result<()> {
         if pos == (0, self.real_pos.1 + 1) {
             if force_wrap || self.real_pos.0 != self.term_width() {
                 queue!(self.writer, Print("\r\n"))?;
@@ -153,7 +195,10 @@ impl<W: Write, C: Canvas> Drawer<'_, '_, W, C> {
                 // we need to do it this way to trigger terminal line wrapping
             }
         } else {
-            if pos.0 != self.real_pos.0 {
+            if pos.0 == self.real_pos.0 {
+            } else if pos.0 == 0 {
+                queue!(self.writer, Print('\r'))?;
+            } else {
                 queue!(self.writer, MoveToColumn(pos.0))?;
             }
             if pos.1 > self.real_pos.1 {
@@ -169,18 +214,19 @@ impl<W: Write, C: Canvas> Drawer<'_, '_, W, C> {
     }
 
     pub fn move_to_cur_pos(&mut self, force_wrap: bool) -> Result<()> {
+        self.flush()?;
         if self.pos != self.real_pos {
             if self.pos.0 < self.term_width() {
-                self.move_to_pos(self.pos, force_wrap)?;
+                self.move_cursor(self.pos, force_wrap)?;
             } else {
                 // oh tricky
                 // in order to get back to the very very edge of the screen, we have to reprint the
                 // char just before
                 // TODO what about when it is a multi width char
                 let pos = (self.term_width() - 1, self.pos.1);
-                self.move_to_pos(pos, force_wrap)?;
+                self.move_cursor(pos, force_wrap)?;
                 if let Some(cell) = self.canvas.get_cell(pos) {
-                    self.draw_cell(&cell.clone(), true)?;
+                    self.draw_cell_now(&cell.clone(), true)?;
                 }
             }
         }
@@ -200,6 +246,7 @@ impl<W: Write, C: Canvas> Drawer<'_, '_, W, C> {
     }
 
     pub fn reset_colours(&mut self) -> Result<()> {
+        self.flush()?;
         self.fg = Color::Reset;
         self.bg = Color::Reset;
         self.underline_color = Color::Reset;
@@ -238,6 +285,16 @@ impl<W: Write, C: Canvas> Drawer<'_, '_, W, C> {
         // clear the rest of this line
         let width = self.term_width();
         if self.pos.0 < width {
+            if force {
+                // the canvas may not know what is really there, so do it now
+                self.flush()?;
+            } else {
+                let blank = Self::blank_cell(cell);
+                for x in self.pos.0 .. width {
+                    self.stage_cell((x, self.pos.1), blank.clone());
+                }
+                return Ok(())
+            }
 
             let cells = self.canvas.get_cell_range_mut(self.pos, (width, self.pos.1));
 
@@ -263,6 +320,7 @@ impl<W: Write, C: Canvas> Drawer<'_, '_, W, C> {
     }
 
     pub fn clear_to_end_of_screen(&mut self, cell: Option<&Cell>) -> Result<()> {
+        self.flush()?;
         // clear everything from cursor onwards
         let width = self.term_width();
         let height = self.term_height();
@@ -292,6 +350,7 @@ impl<W: Write, C: Canvas> Drawer<'_, '_, W, C> {
     }
 
     pub fn write_raw(&mut self, data: &[u8], pos: Option<(u16, u16)>) -> Result<()> {
+        // the canvas does not track this, so anything pending has to go out first
         self.move_to_cur_pos(false)?;
         self.writer.write_all(data)?;
         if let Some(pos) = pos {
@@ -321,6 +380,35 @@ impl<W: Write, C: Canvas> Drawer<'_, '_, W, C> {
     // }
 
     pub fn draw_cell(&mut self, cell: &Cell, force: bool) -> Result<()> {
+        if force {
+            self.flush()?;
+            return self.draw_cell_now(cell, force)
+        }
+
+        let cell_width = cell.text().width() as u16;
+        let width = self.term_width();
+
+        let mut pos = self.pos;
+        if pos.0 + cell_width > width {
+            // not actually enough space to fit this char
+            for x in pos.0 .. width {
+                self.stage_cell((x, pos.1), Cell::EMPTY);
+            }
+            // wrap to next line
+            pos = (0, pos.1 + 1);
+        }
+
+        self.stage_cell(pos, cell.clone());
+        // the rest of a wide char
+        for x in pos.0 + 1 .. pos.0 + cell_width {
+            self.stage_cell((x, pos.1), Cell::EMPTY);
+        }
+        pos.0 += cell_width;
+        self.pos = pos;
+        Ok(())
+    }
+
+    fn draw_cell_now(&mut self, cell: &Cell, force: bool) -> Result<()> {
         let cell_width = cell.text().width() as u16;
         let will_wrap = self.pos.0 + cell_width > self.term_width();
 
@@ -352,6 +440,158 @@ impl<W: Write, C: Canvas> Drawer<'_, '_, W, C> {
         Ok(())
     }
 
+    fn blank_cell(cell: Option<&Cell>) -> Cell {
+        let mut blank = Cell::EMPTY;
+        if let Some(cell) = cell {
+            blank.style = cell.style.clone();
+        }
+        blank
+    }
+
+    fn stage_cell(&mut self, (x, y): (u16, u16), cell: Cell) {
+        let width = self.term_width() as usize;
+        let row = self.back.entry(y).or_insert_with(|| vec![None; width]);
+        if let Some(c) = row.get_mut(x as usize) {
+            *c = Some(cell);
+        }
+    }
+
+    // whether the terminal needs to be updated to show this cell
+    fn cell_changed(&self, pos: (u16, u16), cell: &Cell) -> bool {
+        match self.canvas.get_cell(pos) {
+            // blanks don't care about e.g. the fg
+            Some(front) if *cell == Cell::EMPTY => !super::cell_is_empty(front),
+            Some(front) => front != cell,
+            // off the canvas
+            None => false,
+        }
+    }
+
+    fn style_is_current(&self, style: &Style) -> bool {
+        style.modifier == self.modifier
+            && style.fg.unwrap_or(Color::Reset) == self.fg
+            && style.bg.unwrap_or(Color::Reset) == self.bg
+            && style.underline.unwrap_or_default() == self.underline
+            && style.underline_color.unwrap_or(Color::Reset) == self.underline_color
+            && style.hyperlink == self.hyperlink
+    }
+
+    // get the cursor to pos ready to print a cell
+    fn move_cursor_for_print(&mut self, (x, y): (u16, u16)) -> Result<()> {
+        if self.real_pos == (x, y) {
+            return Ok(())
+        }
+
+        // if only skipping over a few cells, print them again instead
+        if self.real_pos.1 == y && self.real_pos.0 < x && x - self.real_pos.0 <= MAX_REPRINT_CELLS {
+            let start = self.real_pos;
+            let gap = self.canvas.get_cell_range(start, (x, y));
+            let reprint = gap.len() == (x - start.0) as usize
+                && gap.iter().all(|c| c.text().len() == 1 && c.width() == 1 && self.style_is_current(&c.style));
+            if reprint {
+                let text: String = gap.iter().map(|c| c.text()).collect();
+                queue!(self.writer, Print(text))?;
+                self.real_pos = (x, y);
+                return Ok(())
+            }
+        }
+
+        self.move_cursor((x, y), false)
+    }
+
+    // if the line ends in blanks, it may be cheaper to erase them than print them
+    fn find_erasable_tail(&self, y: u16, row: &[Option<Cell>]) -> Option<(u16, Cell)> {
+        let cell_at = |x: usize| row[x].as_ref().or_else(|| self.canvas.get_cell((x as u16, y)));
+
+        let blank = cell_at(row.len().checked_sub(1)?)?;
+        if blank.text() != " " {
+            return None
+        }
+
+        let mut start = None;
+        // what it would cost to print the changed cells instead
+        let mut cost = 0;
+        let mut prev_changed = false;
+        for x in (0 .. row.len()).rev() {
+            if cell_at(x) != Some(blank) {
+                break
+            }
+            if x > 0 && cell_at(x - 1).is_some_and(|c| c.width() > 1) {
+                // this is the other half of a wide char
+                break
+            }
+            let changed = row[x].as_ref().is_some_and(|c| self.cell_changed((x as u16, y), c));
+            if changed {
+                cost += 1;
+                if !prev_changed {
+                    cost += MOVE_COST;
+                }
+                start = Some(x as u16);
+            }
+            prev_changed = changed;
+        }
+
+        // the move to the start is paid either way
+        if cost.saturating_sub(MOVE_COST) > ERASE_COST {
+            start.map(|start| (start, blank.clone()))
+        } else {
+            None
+        }
+    }
+
+    fn flush_row(&mut self, y: u16, mut row: Vec<Option<Cell>>) -> Result<()> {
+        let width = row.len() as u16;
+        let tail = self.find_erasable_tail(y, &row);
+        let end = tail.as_ref().map_or(width, |(start, _)| *start);
+
+        let mut x = 0;
+        while x < end {
+            let Some(cell) = row[x as usize].take() else {
+                x += 1;
+                continue
+            };
+            let cell_width = cell.text().width() as u16;
+
+            if self.cell_changed((x, y), &cell) {
+                self.move_cursor_for_print((x, y))?;
+                self.print_cell(&cell)?;
+                self.canvas.set_cell((x, y), &cell);
+                self.real_pos = (x + cell_width, y);
+            }
+
+            // the rest of a wide char is covered by it
+            for x in x + 1 .. (x + cell_width).min(width) {
+                self.canvas.set_cell((x, y), &Cell::EMPTY);
+                row[x as usize] = None;
+            }
+            x += cell_width.max(1);
+        }
+
+        if let Some((start, blank)) = tail {
+            self.move_cursor((start, y), true)?;
+            self.print_style_of_cell(&blank)?;
+            queue!(self.writer, Clear(ClearType::UntilNewLine))?;
+            for x in start .. width {
+                self.canvas.set_cell((x, y), &blank);
+            }
+        }
+        Ok(())
+    }
+
+    // write out whatever has changed since the last flush
+    pub fn flush(&mut self) -> Result<()> {
+        if self.back.is_empty() {
+            return Ok(())
+        }
+
+        let pos = self.pos;
+        for (y, row) in std::mem::take(&mut self.back) {
+            self.flush_row(y, row)?;
+        }
+        self.pos = pos;
+        Ok(())
+    }
+
     pub fn print_style_of_cell(&mut self, cell: &Cell) -> Result<()> {
         let cell_modifier = cell.style.modifier;
         let cell_fg = cell.style.fg.unwrap_or(Color::Reset);
@@ -275,6 +275,9 @@ impl Node {
 pub struct Nodes {
     pub(super) map: HashMap<NodeId, Node>,
     root: Layout,
+    // nodes drawn outside the main tree, e.g. by the prompt
+    // and whether they were created just for that
+    detached: HashMap<NodeId, bool>,
     counter: usize,
     pub size: Cell<(u16, u16)>,
 }
@@ -354,6 +357,7 @@ impl Nodes {
     /// Remove a node
     pub fn remove(&mut self, id: NodeId) -> Option<Node> {
         self.remove_child_from_parent(id);
+        self.detached.remove(&id);
         let node = self.map.remove(&id);
         if let Some(Node{ kind: NodeKind::Layout(layout), .. }) = &node {
             // orphan the children
@@ -380,15 +384,59 @@ impl Nodes {
         self.root.children.push(child_id);
     }
 
+    /// Take a node out of the main tree so that something else can draw it.
+    /// It and its descendants are kept when messages are cleared.
+    /// An `owned` node was created to be detached and is removed when it is released.
+    pub fn detach(&mut self, id: NodeId, owned: bool) {
+        self.remove_child_from_parent(id);
+        if let Some(node) = self.map.get_mut(&id) {
+            node.has_parent = true;
+        }
+        self.detached.entry(id).or_insert(owned);
+    }
+
+    /// Stop drawing a detached node outside the main tree.
+    /// Owned nodes are removed, others go back to the top level.
+    pub fn release(&mut self, id: NodeId) {
+        match self.detached.remove(&id) {
+            Some(true) => { self.remove(id); },
+            Some(false) if self.map.contains_key(&id) => {
+                self.add_child(id);
+                if let Some(node) = self.map.get_mut(&id) {
+                    node.has_parent = false;
+                }
+            },
+            _ => (),
+        }
+    }
+
+    fn get_detached_descendants(&self) -> HashSet<NodeId> {
+        let mut ids = HashSet::new();
+        let mut stack: Vec<_> = self.detached.keys().copied().collect();
+        while let Some(id) = stack.pop() {
+            if ids.insert(id) && let Some(Node{kind: NodeKind::Layout(layout), ..}) = self.map.get(&id) {
+                stack.extend(layout.children.iter().copied());
+            }
+        }
+        ids
+    }
+
     /// Clear all top-level children (and their descendants).
     pub fn clear_all(&mut self) {
-        self.map.clear();
+        let keep = self.get_detached_descendants();
+        self.map.retain(|id, _node| keep.contains(id));
         self.root.children.clear();
     }
 
+    /// Stop keeping detached nodes around when clearing.
+    pub fn clear_detached(&mut self) {
+        self.detached.clear();
+    }
+
     /// Remove non-persistent top-level nodes.
     pub fn clear_non_persistent(&mut self) {
-        let cleared: HashSet<_> = self.map.extract_if(|_id, node| !node.persist).map(|(id, _node)| id).collect();
+        let keep = self.get_detached_descendants();
+        let cleared: HashSet<_> = self.map.extract_if(|id, node| !node.persist && !keep.contains(id)).map(|(id, _node)| id).collect();
         for layout in self.get_layouts_mut() {
             layout.children.retain(|id| !cleared.contains(id));
         }
@@ -449,6 +497,48 @@ impl Nodes {
         drawer.clear_to_end_of_line(None, false)
     }
 
+    /// Refresh a detached node.
+    /// It takes up the full width unless its own width constraints say otherwise.
+    pub fn refresh_detached(&self, id: NodeId, max_width: u16, max_height: Option<u16>) -> Option<(u16, u16)> {
+        let node = self.map.get(&id)?;
+        let width = node.width_spec.into_size(Some(max_width), Some(max_width)).size.min(max_width);
+        Some(node.refresh(&self.map, width, max_height, false, None).0)
+    }
+
+    /// The column where the last row of a node ends after a refresh, e.g. where the buffer goes after a prompt.
+    pub fn get_end_column(&self, id: NodeId) -> u16 {
+        let Some(node) = self.map.get(&id)
+            else { return 0 };
+        let NodeKind::Layout(layout) = &node.kind
+            else { return node.get_size(false).0 };
+
+        let mut visible = layout.children.iter()
+            .filter_map(|id| self.map.get(id))
+            .filter(|node| node.is_visible(&self.map, false));
+        match layout.direction {
+            Direction::Vertical => visible.next_back().map_or(0, |node| self.get_end_column(node.id)),
+            Direction::Horizontal => {
+                let Some(last) = visible.next_back()
+                    else { return 0 };
+                visible.map(|node| node.get_size(false).0).sum::<u16>() + self.get_end_column(last.id)
+            },
+        }
+    }
+
+    /// Render a detached node at the current position, without clearing the rest of the line.
+    pub fn render_detached<W: Write, C: Canvas>(
+        &self,
+        id: NodeId,
+        drawer: &mut Drawer<W, C>,
+        pad: bool,
+    ) -> std::io::Result<()> {
+        if let Some(node) = self.map.get(&id) && node.is_visible(&self.map, false) {
+            let mut renderer = NodeRenderer::new(node, &self.map, false);
+            renderer.render(drawer, false, pad, NoRendererCallback::None)?;
+        }
+        Ok(())
+    }
+
     pub fn render_node<W: Write, C: Canvas>(
         &self,
         node: &Node,
@@ -1,53 +1,241 @@
 use crate::tui::text::{TextRenderer, Renderer, NoRendererCallback};
 use crate::tui::{Drawer, Canvas};
+use crate::tui::layout::{Nodes, NodeId};
 use std::io::{Write};
+use crossterm::{
+    queue,
+    cursor::{MoveTo, SavePosition, RestorePosition},
+    terminal::{Clear, ClearType},
+    style::ResetColor,
+};
+
+pub const DEFAULT_NAME: &str = "default";
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, strum::EnumString, strum::Display)]
+#[strum(ascii_case_insensitive, serialize_all = "snake_case")]
+pub enum Placement {
+    // the last rows of the terminal, below the prompt and messages
+    // this is part of the ui so it scrolls away with command output
+    #[default]
+    Bottom,
+    // pinned to the first rows of the terminal, above a scroll region
+    PinnedTop,
+    // pinned to the last rows of the terminal, below a scroll region
+    PinnedBottom,
+    // drawn just above the prompt
+    Inline,
+}
+
+impl Placement {
+    pub fn is_pinned(self) -> bool {
+        matches!(self, Self::PinnedTop | Self::PinnedBottom)
+    }
+}
+
+#[derive(Debug)]
+pub enum StatusBarContents {
+    Widget(super::widget::Widget),
+    // a node detached from the message tree
+    Layout(NodeId),
+}
+
+#[derive(Debug)]
+pub struct Bar {
+    pub name: String,
+    pub contents: StatusBarContents,
+    pub placement: Placement,
+    height: u16,
+}
+
+impl Bar {
+    pub fn new(name: String, contents: StatusBarContents, placement: Placement) -> Self {
+        Self {
+            name,
+            contents,
+            placement,
+            height: 0,
+        }
+    }
+
+    pub fn get_layout_id(&self) -> Option<NodeId> {
+        if let StatusBarContents::Layout(id) = &self.contents {
+            Some(*id)
+        } else {
+            None
+        }
+    }
+
+    fn refresh(&mut self, nodes: &Nodes, width: u16) {
+        self.height = match &mut self.contents {
+            StatusBarContents::Widget(widget) => {
+                widget.line_count = widget.get_height_for_width(width, None);
+                widget.line_count
+            },
+            StatusBarContents::Layout(id) => nodes.refresh_detached(*id, width, None).map_or(0, |size| size.1),
+        };
+    }
+
+    fn render<W :Write, C: Canvas>(
+        &self,
+        nodes: &Nodes,
+        drawer: &mut Drawer<W, C>,
+    ) -> std::io::Result<()> {
+
+        match &self.contents {
+            StatusBarContents::Widget(inner) => {
+                TextRenderer::new(
+                    &inner.inner,
+                    0,
+                    None,
+                    drawer.term_width() as _,
+                    None,
+                    None,
+                    |parano| inner.inner.highlights.get_for_parano(parano).iter(),
+                ).render(drawer, false, true, NoRendererCallback::None)?;
+            },
+            StatusBarContents::Layout(id) => {
+                nodes.render_detached(*id, drawer, true)?;
+            },
+        }
+        drawer.clear_to_end_of_line(None, false)?;
+        Ok(())
+    }
+}
 
 #[derive(Default)]
 pub struct StatusBar {
-    pub inner: Option<super::widget::Widget>,
+    // drawn in this order
+    bars: Vec<Bar>,
     pub dirty: bool,
+    // e.g. while the transient prompt is shown
+    pub hide_unpinned: bool,
 }
 
 impl StatusBar {
     pub fn reset(&mut self) {
-        if let Some(widget) = &mut self.inner {
-            widget.line_count = 0;
+        for bar in &mut self.bars {
+            bar.height = 0;
         }
         self.dirty = true;
     }
 
-    pub fn refresh(&mut self, width: u16) {
-        if let Some(widget) = &mut self.inner {
-            widget.line_count = widget.get_height_for_width(width, None);
+    pub fn get(&self, name: &str) -> Option<&Bar> {
+        self.bars.iter().find(|bar| bar.name == name)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Bar> {
+        self.bars.iter_mut().find(|bar| bar.name == name)
+    }
+
+    // replaces any existing one with the same name, keeping its position
+    pub fn set(&mut self, bar: Bar) -> Option<Bar> {
+        self.dirty = true;
+        if let Some(old) = self.get_mut(&bar.name) {
+            Some(std::mem::replace(old, bar))
+        } else {
+            self.bars.push(bar);
+            None
         }
     }
 
-    pub fn get_height(&self) -> u16 {
-        self.inner.as_ref().map_or(0, |w| w.line_count)
+    pub fn remove(&mut self, name: &str) -> Option<Bar> {
+        let index = self.bars.iter().position(|bar| bar.name == name)?;
+        self.dirty = true;
+        Some(self.bars.remove(index))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=&Bar> {
+        self.bars.iter()
+    }
+
+    pub fn has_layouts(&self) -> bool {
+        self.bars.iter().any(|bar| bar.get_layout_id().is_some())
     }
 
-    pub fn is_visible(&self) -> bool {
-        self.inner.is_some()
+    fn iter_visible(&self, placement: Placement) -> impl Iterator<Item=&Bar> {
+        let hidden = self.hide_unpinned && !placement.is_pinned();
+        self.bars.iter().filter(move |bar| !hidden && bar.placement == placement)
     }
 
-    pub fn render<W :Write, C: Canvas>(
+    pub fn refresh(&mut self, nodes: &Nodes, width: u16) {
+        for bar in &mut self.bars {
+            bar.refresh(nodes, width);
+        }
+    }
+
+    pub fn get_height(&self, placement: Placement) -> u16 {
+        self.iter_visible(placement).map(|bar| bar.height).sum()
+    }
+
+    // absolute position of the bar
+    pub fn get_geometry(&self, name: &str, (width, height): (u16, u16), top_y: u16) -> Option<super::rect::Rect> {
+        let bar = self.get(name)?;
+        let pinned_bottom = height.saturating_sub(self.get_height(Placement::PinnedBottom));
+        let mut y = match bar.placement {
+            Placement::PinnedTop => 0,
+            Placement::PinnedBottom => pinned_bottom,
+            Placement::Bottom => pinned_bottom.saturating_sub(self.get_height(Placement::Bottom)),
+            Placement::Inline => top_y,
+        };
+        for other in self.iter_visible(bar.placement) {
+            if std::ptr::eq(other, bar) {
+                return (bar.height > 0).then_some(super::rect::Rect{
+                    x: 0,
+                    y,
+                    width,
+                    height: bar.height,
+                })
+            }
+            y += other.height;
+        }
+        None
+    }
+
+    // for the bars that are drawn as part of the ui
+    pub fn render_unpinned<W :Write, C: Canvas>(
         &self,
+        placement: Placement,
+        nodes: &Nodes,
         drawer: &mut Drawer<W, C>,
     ) -> std::io::Result<()> {
-
-        let Some(inner) = &self.inner
-            else { return Ok(()) };
-
-        TextRenderer::new(
-            &inner.inner,
-            0,
-            None,
-            drawer.term_width() as _,
-            None,
-            None,
-            |parano| inner.inner.highlights.get_for_parano(parano).iter(),
-        ).render(drawer, false, true, NoRendererCallback::None)?;
-        drawer.clear_to_end_of_line(None, false)?;
+        for (i, bar) in self.iter_visible(placement).filter(|bar| bar.height > 0).enumerate() {
+            if i > 0 {
+                drawer.goto_newline(None)?;
+            }
+            bar.render(nodes, drawer)?;
+        }
         Ok(())
     }
+
+    // these are drawn using absolute positions as they are outside the scroll region
+    pub fn render_pinned<W :Write>(
+        &self,
+        nodes: &Nodes,
+        writer: &mut W,
+        (width, height): (u16, u16),
+    ) -> std::io::Result<()> {
+
+        queue!(writer, SavePosition, ResetColor)?;
+
+        let bottom = height.saturating_sub(self.get_height(Placement::PinnedBottom));
+        for (mut y, placement) in [(0, Placement::PinnedTop), (bottom, Placement::PinnedBottom)] {
+            for bar in self.iter_visible(placement).filter(|bar| bar.height > 0) {
+                for row in y .. y + bar.height {
+                    queue!(writer, MoveTo(0, row), Clear(ClearType::CurrentLine))?;
+                }
+                queue!(writer, MoveTo(0, y))?;
+
+                // the rows have just been cleared so start with a blank canvas
+                let mut canvas = super::Buffer::default();
+                canvas.resize(super::rect::Rect{x: 0, y: 0, width, height: bar.height});
+                let mut drawer = Drawer::new(&mut canvas, writer, (0, 0));
+                bar.render(nodes, &mut drawer)?;
+                drawer.reset_colours()?;
+
+                y += bar.height;
+            }
+        }
+
+        queue!(writer, RestorePosition)
+    }
 }
@@ -9,9 +9,12 @@ use anyhow::Result;
 use crate::keybind::{Event};
 use crate::print_lock::{PrintLock, PrintLockGuard};
 use nix::sys::termios;
-use crate::shell::{Shell, signals::sigchld::PidMap, ParserOptions};
-use crate::lua::{LuaWrapper, EventCallbacks};
+use crate::shell::{Shell, signals::sigchld::PidMap, ParserOptions, ParseTree};
+use crate::lua::{LuaWrapper, EventCallbacks, EventType};
 pub mod buffer;
+pub mod abbr;
+pub mod frame_scheduler;
+pub mod options;
 
 use crossterm::{
     terminal::{Clear, ClearType, BeginSynchronizedUpdate, EndSynchronizedUpdate},
@@ -44,11 +47,14 @@ pub struct _Ui {
     pub events: crate::event_stream::EventController,
     pub has_foreground_process: tokio::sync::Mutex<()>,
     pub print_lock: PrintLock,
-    pub is_drawing: Cell<bool>,
+    pub frame_scheduler: frame_scheduler::FrameScheduler,
     pub runtime: crate::async_runtime::Runtime,
     pub callbacks_are_scheduled: Cell<bool>,
     pub scheduled_callback_notify: tokio::sync::Notify,
     pub event_callbacks: EventCallbacks,
+    // listeners, watches, tasks etc started by the lua config
+    // are cancelled when it is reloaded
+    pub config_scope: RefCell<crate::canceller::Scope>,
 }
 
 pub struct UiInner {
@@ -60,6 +66,9 @@ pub struct UiInner {
     pub keybind_layer_counter: usize,
 
     pub buffer: buffer::Buffer,
+    pub abbreviations: abbr::Abbreviations,
+    pub options: options::Options,
+    pub transient_prompt: Option<TransientPrompt>,
     pub status_bar: crate::tui::status_bar::StatusBar,
 
     pub stdout: std::io::Stdout,
@@ -81,6 +90,20 @@ pub enum CursorStyle {
     Set(SetCursorStyle),
 }
 
+// what the prompt of an accepted command is replaced with
+#[derive(Clone)]
+pub enum TransientPrompt {
+    Widget(crate::tui::widget::Widget),
+    // called with the accepted buffer and returns widget options
+    Callback(LuaFunction),
+}
+
+// the prompt etc that the transient prompt temporarily replaced
+struct SavedPrompt {
+    prompt_mode: crate::tui::command_line::PromptMode,
+    rprompt_mode: crate::tui::command_line::RightPromptMode,
+}
+
 pub type WeakUi = std::rc::Weak<_Ui>;
 
 impl Ui {
@@ -100,6 +123,9 @@ impl Ui {
             tui: Default::default(),
             cmdline: Default::default(),
             buffer: buffer::Buffer::new(),
+            abbreviations: Default::default(),
+            options: Default::default(),
+            transient_prompt: None,
             status_bar: Default::default(),
             keybinds: Default::default(),
             keybind_layer_counter: Default::default(),
@@ -125,11 +151,12 @@ impl Ui {
             shell,
             has_foreground_process: Default::default(),
             print_lock: Default::default(),
-            is_drawing: Default::default(),
+            frame_scheduler: Default::default(),
             runtime,
             callbacks_are_scheduled: Cell::default(),
             scheduled_callback_notify: tokio::sync::Notify::new(),
             event_callbacks: Default::default(),
+            config_scope: Default::default(),
         };
         let ui = Self(Rc::new(ui));
         ui.lua.ui.replace(ui.downgrade());
@@ -138,6 +165,10 @@ impl Ui {
     }
 
 
+    pub fn get_config_scope(&self) -> crate::canceller::SharedCancellable {
+        self.config_scope.borrow().get()
+    }
+
     pub fn try_borrow(&self) -> Result<std::cell::Ref<'_, UiInner>, BorrowError> {
         self.inner.try_borrow()
     }
@@ -146,6 +177,20 @@ impl Ui {
         self.inner.try_borrow_mut()
     }
 
+    // disable everything from the lua config then run it again
+    pub async fn reload_lua(&self) -> Result<()> {
+        self.lua.unload_config().await?;
+        self.config_scope.take().cancel();
+        self.event_callbacks.clear();
+        self.try_borrow_mut()?.clear_lua_state();
+        self.frame_scheduler.options.set(Default::default());
+
+        self.report_error(self.lua.load_config().await)?;
+        self.event_callbacks.init(self).await?;
+        self.queue_draw();
+        Ok(())
+    }
+
     pub async fn start_cmd(&self, buffer: Option<&BStr>) -> Result<()> {
         self.event_callbacks.precmd(self, buffer).await?;
         self.draw().await
@@ -164,18 +209,100 @@ impl Ui {
     }
 
     pub fn queue_draw(&self) {
-        if !crate::is_forked() {
-            let old = self.is_drawing.replace(true);
-            if !old {
-                self.events.queue_draw();
+        if crate::is_forked() {
+            return
+        }
+
+        match self.frame_scheduler.request() {
+            frame_scheduler::DrawRequest::Now => self.events.queue_draw(),
+            frame_scheduler::DrawRequest::After(delay, generation) => {
+                let ui = self.downgrade();
+                crate::spawn_and_log::<_, _, anyhow::Error>(self, async move {
+                    tokio::time::sleep(delay).await;
+                    if let Some(ui) = ui.upgrade() && ui.frame_scheduler.timer_fired(generation) {
+                        ui.events.queue_draw();
+                    }
+                    Ok(())
+                });
+            },
+            frame_scheduler::DrawRequest::Pending => (),
+        }
+    }
+
+    pub fn get_parse_tree(&self) -> Result<Rc<ParseTree>> {
+        let (contents, tree) = {
+            let ui = self.try_borrow()?;
+            (ui.buffer.get_contents().clone(), ui.buffer.parse_tree.clone())
+        };
+        if let Some(tree) = &tree && tree.contents == contents {
+            return Ok(tree.clone())
+        }
+
+        // don't hold the ui while parsing
+        let (tree, changed) = self.shell.update_parse_tree(tree.as_deref(), contents);
+        let tree = Rc::new(tree);
+        let mut ui = self.try_borrow_mut()?;
+        ui.buffer.parse_tree = Some(tree.clone());
+        ui.buffer.parse_change_pending |= changed;
+        Ok(tree)
+    }
+
+    // expands the abbreviation in the word just before the cursor, if any
+    // returns whether the cursor was placed using the cursor marker
+    pub async fn expand_abbreviation(&self) -> Result<Option<bool>> {
+        if self.try_borrow()?.abbreviations.is_empty() {
+            return Ok(None)
+        }
+
+        let tree = self.get_parse_tree()?;
+        let cursor = self.try_borrow()?.buffer.cursor_byte_pos();
+        let commands = self.shell.parse_commands(tree.contents.as_ref(), &tree.tokens);
+
+        // word positions are 1-based
+        let Some((word, command_position)) = commands.iter()
+            .flat_map(|cmd| cmd.command.iter().map(|w| (w, true)).chain(cmd.argv.iter().skip(1).map(|w| (w, false))))
+            .find(|(w, _)| w.finish == cursor)
+            else { return Ok(None) };
+
+        let has_marker = {
+            let mut ui = self.try_borrow_mut()?;
+            let Some(abbr) = ui.abbreviations.find(word.text.as_ref(), command_position)
+                else { return Ok(None) };
+            let (expansion, marker) = abbr.expand();
+
+            let start = ui.buffer.char_pos(word.start - 1);
+            let len = ui.buffer.char_pos(word.finish) - start;
+            ui.buffer.splice_at(start, &expansion, len, true);
+            let cursor = word.start - 1 + marker.unwrap_or(expansion.len());
+            let cursor = ui.buffer.char_pos(cursor);
+            ui.buffer.set_cursor(cursor);
+            marker.is_some()
+        };
+
+        self.event_callbacks.buffer_change(self).await?;
+        self.event_callbacks.buffer_cursor_move(self).await?;
+        self.queue_draw();
+        Ok(Some(has_marker))
+    }
+
+    async fn trigger_parse_change(&self) -> Result<()> {
+        // only bother parsing if someone is listening
+        if self.event_callbacks.has_callbacks(EventType::parse_change) {
+            self.get_parse_tree()?;
+            if std::mem::take(&mut self.try_borrow_mut()?.buffer.parse_change_pending) {
+                self.event_callbacks.parse_change(self).await?;
             }
         }
+        Ok(())
     }
 
     pub async fn draw(&self) -> Result<()> {
-        self.is_drawing.set(false);
+        let frame = self.frame_scheduler.begin_frame();
+        // this batches up all the buffer changes since the last draw
+        self.trigger_parse_change().await?;
         if let Ok(mut lock) = self.print_lock.try_lock() && lock.get_value() == 0 {
             let resized = self.draw_with_lock(&mut lock).await?;
+            self.frame_scheduler.end_frame(frame);
             if !resized.is_empty() {
                 self.event_callbacks.message_resize(self, &resized).await?;
             }
@@ -187,10 +314,11 @@ impl Ui {
     }
 
     pub fn draw_blocking(&self, force: bool) -> Result<()> {
-        self.is_drawing.set(false);
+        let frame = self.frame_scheduler.begin_frame();
         if let Ok(mut lock) = self.print_lock.try_lock() && lock.get_value() == 0 {
             let mut size = None;
             self.draw_with_lock_blocking(&mut lock, &mut size, None, force)?;
+            self.frame_scheduler.end_frame(frame);
             Ok(())
         } else {
             // the shell will draw it later
@@ -286,6 +414,8 @@ impl Ui {
     }
 
     pub async fn handle_event(&mut self, event: Event, event_buffer: BString) -> Result<bool> {
+        // redraw the buffer etc as soon as possible
+        self.frame_scheduler.note_input();
         match event {
             Event::Key(ev) => self.event_callbacks.key(self, &ev.into(), event_buffer.as_ref()).await?,
             Event::Mouse(ev) => self.event_callbacks.mouse(self, &ev.into(), event_buffer.as_ref()).await?,
@@ -490,6 +620,27 @@ impl Ui {
         Ok(())
     }
 
+    async fn get_transient_prompt(&self, buffer: &BStr) -> Result<Option<crate::tui::widget::Widget>> {
+        let Some(transient) = self.try_borrow()?.transient_prompt.clone()
+            else { return Ok(None) };
+
+        match transient {
+            TransientPrompt::Widget(widget) => Ok(Some(widget)),
+            TransientPrompt::Callback(func) => {
+                let arg = self.lua.create_string(buffer)?;
+                let Some(value) = self.call_lua_fn(false, func, arg).await?
+                    else { return Ok(None) };
+                match crate::lua::make_transient_prompt(&self.lua, value) {
+                    Ok(widget) => Ok(widget),
+                    err => {
+                        self.report_error(err)?;
+                        Ok(None)
+                    },
+                }
+            },
+        }
+    }
+
     pub async fn accept_line(&mut self) -> Result<bool> {
         if crate::is_forked() {
             return Ok(false)
@@ -517,13 +668,21 @@ impl Ui {
         // time to execute
         if let Some(buffer) = buffer {
             self.event_callbacks.accept_line(self, buffer.as_ref()).await?;
+            let transient = self.get_transient_prompt(buffer.as_ref()).await?;
 
             {
                 let fg_lock = self.has_foreground_process.lock().await;
                 let mut print_lock = self.print_lock.lock_exclusive().await;
 
                 // last draw
+                let saved = match transient {
+                    Some(widget) => Some(self.try_borrow_mut()?.apply_transient_prompt(widget)),
+                    None => None,
+                };
                 crate::log_if_err(self.draw_with_lock(&mut print_lock).await);
+                if let Some(saved) = saved {
+                    self.try_borrow_mut()?.restore_prompt(saved);
+                }
                 self.pre_accept_line(&mut print_lock)?;
                 // acceptline doesn't actually accept the line right now
                 // only when we return control to zle using the trampoline
@@ -819,6 +978,24 @@ impl UiInner {
         crate::log_if_err(crossterm::terminal::disable_raw_mode());
     }
 
+    // forget everything that was set up by the lua config
+    fn clear_lua_state(&mut self) {
+        self.keybinds = vec![Default::default()];
+        self.cmdline.prompt_mode = Default::default();
+        self.cmdline.rprompt_mode = Default::default();
+        self.cmdline.prompt_dirty = true;
+        self.cmdline.rprompt_dirty = true;
+        self.transient_prompt = None;
+        self.status_bar = Default::default();
+        self.status_bar.dirty = true;
+        self.abbreviations = Default::default();
+        self.options.clear();
+        self.tui.clear_lua_state();
+        self.buffer.clear_highlights();
+        self.buffer.marks.clear_namespace(None);
+        self.dirty = true;
+    }
+
     fn reset(&mut self) {
         self.buffer.reset();
         self.tui.reset();
@@ -850,6 +1027,8 @@ impl UiInner {
             self.stdout,
             Clear(ClearType::FromCursorDown),
         )?;
+        // that will have cleared the bottom status bars too
+        self.tui.draw_pinned_status_bars(&mut self.stdout, &self.status_bar)?;
         if end_sync {
             execute!(
                 self.stdout,
@@ -862,6 +1041,31 @@ impl UiInner {
     }
 
 
+    // scrollback only keeps the transient prompt and the command
+    fn apply_transient_prompt(&mut self, widget: crate::tui::widget::Widget) -> SavedPrompt {
+        use crate::tui::command_line::{PromptMode, RightPromptMode};
+        let saved = SavedPrompt {
+            prompt_mode: std::mem::replace(&mut self.cmdline.prompt_mode, PromptMode::Custom{widget}),
+            rprompt_mode: std::mem::replace(&mut self.cmdline.rprompt_mode, RightPromptMode::None),
+        };
+        self.cmdline.prompt_dirty = true;
+        self.cmdline.rprompt_dirty = true;
+        // pinned status bars are not part of the scrollback so they can stay
+        self.status_bar.hide_unpinned = true;
+        self.status_bar.dirty = true;
+        self.tui.clear_non_persistent();
+        saved
+    }
+
+    fn restore_prompt(&mut self, saved: SavedPrompt) {
+        self.cmdline.prompt_mode = saved.prompt_mode;
+        self.cmdline.rprompt_mode = saved.rprompt_mode;
+        self.status_bar.hide_unpinned = false;
+        self.cmdline.prompt_dirty = true;
+        self.cmdline.rprompt_dirty = true;
+        self.status_bar.dirty = true;
+    }
+
     fn draw(&mut self, cursor_y: Option<u32>) -> Result<Vec<usize>> {
         let cmdline = self.cmdline.make_command_line(&mut self.buffer);
         let resized = self.tui.draw(
@@ -877,6 +1081,7 @@ impl UiInner {
     }
 
     pub fn destroy(&mut self) {
+        crate::log_if_err(self.tui.reset_scroll_region(&mut self.stdout));
         self.deactivate();
     }
 
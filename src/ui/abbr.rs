@@ -0,0 +1,80 @@
+use bstr::{BStr, BString, ByteSlice};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, strum::EnumString, strum::Display)]
+#[strum(ascii_case_insensitive, serialize_all = "lowercase")]
+pub enum Position {
+    // only the command word, e.g. not arguments
+    #[default]
+    Command,
+    Anywhere,
+}
+
+#[derive(Debug)]
+pub struct Abbreviation {
+    pub word: BString,
+    pub expansion: BString,
+    pub position: Position,
+    // the pattern as given and the regex compiled from it
+    // which must match the whole word (instead of being compared with `word`)
+    pub regex: Option<(String, regex::bytes::Regex)>,
+    pub cursor_marker: Option<BString>,
+}
+
+impl Abbreviation {
+    fn matches(&self, word: &BStr, command_position: bool) -> bool {
+        if self.position == Position::Command && !command_position {
+            return false
+        }
+        match &self.regex {
+            Some((_, regex)) => regex.is_match(word),
+            None => self.word == word,
+        }
+    }
+
+    // the text to insert and where the cursor goes within it
+    pub fn expand(&self) -> (BString, Option<usize>) {
+        if let Some(marker) = &self.cursor_marker
+            && !marker.is_empty()
+            && let Some(pos) = self.expansion.find(marker)
+        {
+            let mut expansion = self.expansion[..pos].to_owned();
+            expansion.extend_from_slice(&self.expansion[pos + marker.len() ..]);
+            (expansion.into(), Some(pos))
+        } else {
+            (self.expansion.clone(), None)
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Abbreviations {
+    inner: Vec<Abbreviation>,
+}
+
+impl Abbreviations {
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=&Abbreviation> {
+        self.inner.iter()
+    }
+
+    // replaces any existing one with the same word
+    pub fn add(&mut self, abbr: Abbreviation) {
+        self.remove(abbr.word.as_ref());
+        self.inner.push(abbr);
+    }
+
+    pub fn remove(&mut self, word: &BStr) -> bool {
+        let len = self.inner.len();
+        self.inner.retain(|a| a.word != word);
+        self.inner.len() != len
+    }
+
+    // later ones take precedence
+    pub fn find(&self, word: &BStr, command_position: bool) -> Option<&Abbreviation> {
+        self.inner.iter().rev().find(|a| a.matches(word, command_position))
+    }
+}
@@ -1,18 +1,17 @@
 use std::borrow::Cow;
+use std::rc::Rc;
+use std::time::Duration;
 use byteyarn::ByteYarn;
 use std::io::Write;
 use bstr::{BStr, BString, ByteSlice};
-use crate::tui::{Drawer, Canvas, Cell, command_line::RightPromptMode};
+use crate::tui::{Drawer, Canvas, Cell, command_line::RightPromptMode, layout::Nodes};
 use crate::tui::text::{Text, HighlightedRange, Highlight};
 use crate::utils::merge_sort_iter::SortedMergeable;
+use crate::shell::ParseTree;
 pub mod suffix;
-
-#[derive(Debug)]
-pub struct Edit {
-    before: ByteYarn,
-    after: ByteYarn,
-    position: usize,
-}
+pub mod undo;
+pub mod marks;
+use undo::{Edit, UndoTree, Step};
 
 #[derive(Debug, Default)]
 pub struct Buffer {
@@ -21,14 +20,19 @@ pub struct Buffer {
     len: Option<usize>,
     cursor: usize,
 
-    history: Vec<Edit>,
-    history_index: usize,
+    pub undo: UndoTree,
+    pub marks: marks::Marks,
 
     saved_contents: BString,
     saved_cursor: usize,
 
     completion_suffix: Option<(usize, suffix::Suffix)>,
 
+    // cached parse of the contents, see Ui::get_parse_tree
+    pub parse_tree: Option<Rc<ParseTree>>,
+    // parse_change has not been triggered for the latest parse yet
+    pub parse_change_pending: bool,
+
     pub dirty: bool,
     pub highlight_counter: usize,
     pub height: usize,
@@ -62,11 +66,11 @@ impl Buffer {
     }
 
     pub fn get_size(&self, width: usize, initial_indent: usize) -> (usize, usize) {
-        self.contents.get_size(width, initial_indent, [].iter())
+        self.contents.get_size(width, initial_indent, self.marks.highlights.iter())
     }
 
     pub fn get_first_line_width(&self, width: usize, initial_indent: usize) -> usize {
-        self.contents.get_first_line_width(width, initial_indent, [].iter())
+        self.contents.get_first_line_width(width, initial_indent, self.marks.highlights.iter())
     }
 
     pub fn get_len(&mut self) -> usize {
@@ -160,16 +164,11 @@ impl Buffer {
             position: start,
         };
 
-        if self.history_index < self.history.len() {
-            drop(self.history.drain(self.history_index .. ));
-        }
-        self.history.push(edit);
-        self.apply_edit(self.history_index, false);
-        self.history_index += 1;
+        self.apply_edit(&edit, false);
+        self.undo.push(edit);
     }
 
-    fn apply_edit(&mut self, index: usize, reverse: bool) {
-        let edit = &self.history[index];
+    fn apply_edit(&mut self, edit: &Edit, reverse: bool) {
         let (old, new) = if reverse {
             (&edit.after, &edit.before)
         } else {
@@ -178,16 +177,11 @@ impl Buffer {
 
         self.contents.delete_str(0, edit.position, old.len());
         self.contents.insert_str(new.as_bytes().into(), 0, edit.position, true, None);
+        self.marks.shift(edit.position .. edit.position + old.len(), new.len());
         self.len = None;
 
         // calculate the new cursor
-        let end = edit.position + new.len();
-        let bytes = self.get_contents();
-        self.cursor = if bytes.is_ascii() {
-            end.min(bytes.len())
-        } else {
-            bytes.grapheme_indices().take_while(|(s, _, _)| *s < end).count()
-        };
+        self.cursor = self.char_pos(edit.position + new.len());
         self.fix_cursor();
     }
 
@@ -197,19 +191,46 @@ impl Buffer {
         old
     }
 
+    fn apply_steps<I: IntoIterator<Item=Step>>(&mut self, steps: I) -> bool {
+        let mut changed = false;
+        for step in steps {
+            let edits = self.undo.nodes[step.node].edits.clone();
+            if step.reverse {
+                for edit in edits.iter().rev() {
+                    self.apply_edit(edit, true);
+                }
+            } else {
+                for edit in &edits {
+                    self.apply_edit(edit, false);
+                }
+            }
+            changed = true;
+        }
+        if changed {
+            self.completion_suffix.take();
+        }
+        changed
+    }
+
     pub fn move_in_history(&mut self, forward: bool) -> bool {
         self.completion_suffix.take();
-        if forward && self.history_index < self.history.len() {
-            self.apply_edit(self.history_index, false);
-            self.history_index += 1;
-            true
-        } else if !forward && self.history_index > 0 {
-            self.history_index -= 1;
-            self.apply_edit(self.history_index, true);
-            true
+        let step = if forward {
+            self.undo.redo()
         } else {
-            false
-        }
+            self.undo.undo()
+        };
+        self.apply_steps(step)
+    }
+
+    // go to a node in the undo tree
+    pub fn undo_goto(&mut self, node: usize) -> bool {
+        let steps = self.undo.goto(node);
+        self.apply_steps(steps)
+    }
+
+    pub fn undo_goto_time(&mut self, delta: Duration, forward: bool) -> bool {
+        let steps = self.undo.goto_time(delta, forward);
+        self.apply_steps(steps)
     }
 
     pub fn insert_at_cursor(&mut self, data: &[u8]) {
@@ -241,8 +262,8 @@ impl Buffer {
         self.contents.reset();
         self.contents.push_line(b"".into(), None);
         self.len = None;
-        self.history.clear();
-        self.history_index = 0;
+        self.undo = UndoTree::default();
+        self.marks.clear_namespace(None);
         self.height = 0;
         self.cursor = 0;
         self.saved_contents.clear();
@@ -264,6 +285,17 @@ impl Buffer {
             .unwrap_or_else(|| bytes.len())
     }
 
+    // inverse of byte_pos
+    pub fn char_pos(&self, pos: usize) -> usize {
+        let bytes = self.get_contents();
+
+        if bytes.is_ascii() {
+            return pos.min(bytes.len());
+        }
+
+        bytes.grapheme_indices().take_while(|(s, _, _)| *s < pos).count()
+    }
+
     pub fn cursor_byte_pos(&self) -> usize {
         self.byte_pos(self.cursor)
     }
@@ -280,6 +312,7 @@ impl Buffer {
         predisplay: Option<Highlight<usize, Cow<'_, BStr>>>,
         postdisplay: Option<Highlight<usize, Cow<'_, BStr>>>,
         rprompt: &RightPromptMode,
+        nodes: &Nodes,
         rprompt_size: (usize, usize),
         rprompt_dirty: bool,
     ) -> std::io::Result<(u16, u16)> {
@@ -302,6 +335,7 @@ impl Buffer {
             |parano| {
                 (parano == 0).then(||
                     self.contents.highlights.iter()
+                        .sorted_merge_with(self.marks.highlights.iter())
                         .sorted_merge_with(predisplay.iter())
                         .sorted_merge_with(postdisplay.iter())
                 ).into_iter().flatten()
@@ -351,7 +385,7 @@ impl Buffer {
 
             // draw rprompt
             if first {
-                rprompt.render(drawer, rprompt_size, rprompt_dirty)?;
+                rprompt.render(nodes, drawer, rprompt_size, rprompt_dirty)?;
             }
 
             first = false;
@@ -359,7 +393,7 @@ impl Buffer {
 
         // draw rprompt
         if first {
-            rprompt.render(drawer, rprompt_size, rprompt_dirty)?;
+            rprompt.render(nodes, drawer, rprompt_size, rprompt_dirty)?;
         } else {
             drawer.clear_to_end_of_line(None, crate::shell::is_interrupted())?;
         }
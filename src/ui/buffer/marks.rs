@@ -0,0 +1,138 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::ops::Range;
+use bstr::BStr;
+use mlua::prelude::*;
+use crate::tui::text::{HighlightedRange, Highlight, HighlightedRangeSet};
+
+// which way a mark moves when text is inserted exactly at it
+#[derive(Debug, Default, Clone, Copy, PartialEq, strum::EnumString)]
+#[strum(ascii_case_insensitive)]
+pub enum Gravity {
+    // both ends stay after the inserted text
+    #[default]
+    Right,
+    // both ends stay before the inserted text
+    Left,
+    // the range grows to include the inserted text
+    Inner,
+    // the range excludes the inserted text
+    Outer,
+}
+
+impl Gravity {
+    fn start_moves_right(self) -> bool {
+        matches!(self, Self::Right | Self::Outer)
+    }
+
+    fn end_moves_right(self) -> bool {
+        matches!(self, Self::Right | Self::Inner)
+    }
+}
+
+#[derive(Debug)]
+pub struct Mark {
+    // byte offsets, end is exclusive
+    pub start: usize,
+    pub end: usize,
+    pub namespace: usize,
+    pub gravity: Gravity,
+    pub data: Option<LuaValue>,
+    pub highlight: Option<Highlight<usize, Cow<'static, BStr>>>,
+}
+
+fn shift_pos(pos: usize, edit: Range<usize>, new_len: usize, right: bool) -> usize {
+    if pos < edit.start {
+        pos
+    } else if pos > edit.end {
+        pos + new_len - edit.len()
+    } else if edit.is_empty() {
+        // inserted exactly at this position
+        if right { edit.start + new_len } else { edit.start }
+    } else if pos == edit.start {
+        edit.start
+    } else if pos == edit.end || right {
+        edit.start + new_len
+    } else {
+        edit.start
+    }
+}
+
+impl Mark {
+    fn shift(&mut self, edit: Range<usize>, new_len: usize) {
+        self.start = shift_pos(self.start, edit.clone(), new_len, self.gravity.start_moves_right());
+        self.end = shift_pos(self.end, edit, new_len, self.gravity.end_moves_right());
+        self.end = self.end.max(self.start);
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Marks {
+    marks: BTreeMap<usize, Mark>,
+    counter: usize,
+    // highlights attached to marks, kept separate from the buffer highlights
+    // and rebuilt whenever a mark moves
+    pub highlights: HighlightedRangeSet<usize, Cow<'static, BStr>>,
+}
+
+impl Marks {
+
+    pub fn add(&mut self, mark: Mark) -> usize {
+        self.counter += 1;
+        let hl = mark.highlight.is_some();
+        self.marks.insert(self.counter, mark);
+        if hl {
+            self.rebuild_highlights();
+        }
+        self.counter
+    }
+
+    pub fn get(&self, id: usize) -> Option<&Mark> {
+        self.marks.get(&id)
+    }
+
+    pub fn remove(&mut self, id: usize) -> Option<Mark> {
+        let mark = self.marks.remove(&id)?;
+        if mark.highlight.is_some() {
+            self.rebuild_highlights();
+        }
+        Some(mark)
+    }
+
+    pub fn clear_namespace(&mut self, namespace: Option<usize>) {
+        self.marks.retain(|_, m| namespace.is_some_and(|ns| m.namespace != ns));
+        self.rebuild_highlights();
+    }
+
+    // marks overlapping the range, in order of id
+    pub fn iter(&self, namespace: Option<usize>, range: Option<Range<usize>>) -> impl Iterator<Item=(usize, &Mark)> {
+        self.marks.iter()
+            .filter(move |(_, m)| namespace.is_none_or(|ns| m.namespace == ns))
+            .filter(move |(_, m)| range.as_ref().is_none_or(|r| m.start <= r.end && r.start <= m.end))
+            .map(|(id, m)| (*id, m))
+    }
+
+    pub fn shift(&mut self, edit: Range<usize>, new_len: usize) {
+        if self.marks.is_empty() {
+            return
+        }
+        for mark in self.marks.values_mut() {
+            mark.shift(edit.clone(), new_len);
+        }
+        self.rebuild_highlights();
+    }
+
+    fn rebuild_highlights(&mut self) {
+        self.highlights.clear();
+        for mark in self.marks.values() {
+            if let Some(hl) = &mark.highlight {
+                self.highlights.push(HighlightedRange{
+                    parano: 0,
+                    start: mark.start,
+                    end: mark.end,
+                    inner: hl.clone(),
+                });
+            }
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use std::time::{Duration, SystemTime};
+use byteyarn::ByteYarn;
+
+#[derive(Debug, Clone)]
+pub struct Edit {
+    pub before: ByteYarn,
+    pub after: ByteYarn,
+    pub position: usize,
+}
+
+#[derive(Debug)]
+pub struct UndoNode {
+    // applied in order to get from the parent to this node
+    pub edits: Vec<Edit>,
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+    // which child redo goes to
+    redo_child: Option<usize>,
+    pub time: SystemTime,
+}
+
+impl UndoNode {
+    fn new(parent: Option<usize>) -> Self {
+        Self {
+            edits: vec![],
+            parent,
+            children: vec![],
+            redo_child: None,
+            time: SystemTime::now(),
+        }
+    }
+}
+
+// a step taken while moving around the tree
+// the edits of the node should be applied, or reversed
+pub struct Step {
+    pub node: usize,
+    pub reverse: bool,
+}
+
+// node 0 is the root and has no edits
+// nodes are never removed so the index is also the order they were made in
+#[derive(Debug)]
+pub struct UndoTree {
+    pub nodes: Vec<UndoNode>,
+    pub current: usize,
+    group_depth: usize,
+    // node that edits in the current group go into
+    group_node: Option<usize>,
+}
+
+impl Default for UndoTree {
+    fn default() -> Self {
+        Self {
+            nodes: vec![UndoNode::new(None)],
+            current: 0,
+            group_depth: 0,
+            group_node: None,
+        }
+    }
+}
+
+impl UndoTree {
+
+    pub fn push(&mut self, edit: Edit) {
+        if self.group_depth > 0 && self.group_node == Some(self.current) {
+            self.nodes[self.current].edits.push(edit);
+            return
+        }
+
+        let index = self.nodes.len();
+        let mut node = UndoNode::new(Some(self.current));
+        node.edits.push(edit);
+        self.nodes.push(node);
+
+        let parent = &mut self.nodes[self.current];
+        parent.children.push(index);
+        parent.redo_child = Some(index);
+        self.current = index;
+
+        if self.group_depth > 0 {
+            self.group_node = Some(index);
+        }
+    }
+
+    pub fn group_begin(&mut self) {
+        self.group_depth += 1;
+    }
+
+    pub fn group_end(&mut self) {
+        self.group_depth = self.group_depth.saturating_sub(1);
+        if self.group_depth == 0 {
+            self.group_node = None;
+        }
+    }
+
+    pub fn undo(&mut self) -> Option<Step> {
+        let node = self.current;
+        let parent = self.nodes[node].parent?;
+        self.nodes[parent].redo_child = Some(node);
+        self.current = parent;
+        Some(Step{ node, reverse: true })
+    }
+
+    pub fn redo(&mut self) -> Option<Step> {
+        let node = self.nodes[self.current].redo_child?;
+        self.current = node;
+        Some(Step{ node, reverse: false })
+    }
+
+    fn ancestors(&self, mut node: usize) -> Vec<usize> {
+        let mut path = vec![node];
+        while let Some(parent) = self.nodes[node].parent {
+            path.push(parent);
+            node = parent;
+        }
+        path
+    }
+
+    // the steps to get from the current node to the target
+    pub fn goto(&mut self, target: usize) -> Vec<Step> {
+        if target >= self.nodes.len() {
+            return vec![]
+        }
+
+        let up = self.ancestors(self.current);
+        let mut down = self.ancestors(target);
+        // find the common ancestor
+        let common = *up.iter().find(|n| down.contains(n)).unwrap();
+
+        let mut steps = vec![];
+        while self.current != common {
+            steps.extend(self.undo());
+        }
+
+        down.truncate(down.iter().position(|&n| n == common).unwrap());
+        for &node in down.iter().rev() {
+            self.nodes[self.current].redo_child = Some(node);
+            steps.extend(self.redo());
+        }
+        steps
+    }
+
+    // like vim :earlier and :later
+    pub fn goto_time(&mut self, delta: Duration, forward: bool) -> Vec<Step> {
+        let now = self.nodes[self.current].time;
+        let target = if forward {
+            let time = now + delta;
+            // the first state at or after the time, otherwise the latest
+            self.nodes.iter().position(|n| n.time >= time).unwrap_or(self.nodes.len() - 1)
+        } else {
+            let time = now.checked_sub(delta).unwrap_or(SystemTime::UNIX_EPOCH);
+            // the last state at or before the time, otherwise the start
+            self.nodes.iter().rposition(|n| n.time <= time).unwrap_or(0)
+        };
+        self.goto(target)
+    }
+}
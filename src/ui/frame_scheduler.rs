@@ -0,0 +1,149 @@
+use std::cell::Cell;
+use std::time::{Instant, Duration};
+
+// draws for anything other than user input are rate limited to idle_fps
+// once there has been no input for this long
+const IDLE_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy)]
+pub struct DrawOptions {
+    // 0 means no limit
+    pub max_fps: f64,
+    pub idle_fps: f64,
+}
+
+impl Default for DrawOptions {
+    fn default() -> Self {
+        Self {
+            max_fps: 60.,
+            idle_fps: 20.,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FrameStats {
+    pub frames: usize,
+    pub requests: usize,
+    // requests that were merged into a frame that was already coming
+    pub coalesced: usize,
+    pub last_frame_time: Duration,
+    pub max_frame_time: Duration,
+    pub total_frame_time: Duration,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+enum State {
+    #[default]
+    Idle,
+    // waiting for a timer before queueing
+    Scheduled,
+    // already sent to the event loop
+    Queued,
+}
+
+pub enum DrawRequest {
+    Now,
+    // queue it after this long, if the generation still matches
+    After(Duration, usize),
+    Pending,
+}
+
+// coalesces draw requests so that busy plugins can't flood the event queue
+#[derive(Default)]
+pub struct FrameScheduler {
+    pub options: Cell<DrawOptions>,
+    state: Cell<State>,
+    generation: Cell<usize>,
+    // set by user input so the next frame is not delayed
+    urgent: Cell<bool>,
+    last_frame: Cell<Option<Instant>>,
+    last_input: Cell<Option<Instant>>,
+    stats: Cell<FrameStats>,
+}
+
+impl FrameScheduler {
+    fn update_stats<F: FnOnce(&mut FrameStats)>(&self, f: F) {
+        let mut stats = self.stats.get();
+        f(&mut stats);
+        self.stats.set(stats);
+    }
+
+    pub fn get_stats(&self) -> FrameStats {
+        self.stats.get()
+    }
+
+    pub fn note_input(&self) {
+        self.urgent.set(true);
+        self.last_input.set(Some(Instant::now()));
+    }
+
+    fn get_interval(&self) -> Duration {
+        let options = self.options.get();
+        let idle = self.last_input.get().is_none_or(|t| t.elapsed() >= IDLE_TIMEOUT);
+        let fps = if idle { options.idle_fps } else { options.max_fps };
+        if fps > 0. {
+            // a tiny fps is effectively never
+            Duration::try_from_secs_f64(1. / fps).unwrap_or(Duration::MAX)
+        } else {
+            Duration::ZERO
+        }
+    }
+
+    fn get_delay(&self) -> Duration {
+        self.last_frame.get().map_or(Duration::ZERO, |t| self.get_interval().saturating_sub(t.elapsed()))
+    }
+
+    pub fn is_frame_due(&self) -> bool {
+        self.get_delay().is_zero()
+    }
+
+    pub fn request(&self) -> DrawRequest {
+        self.update_stats(|s| s.requests += 1);
+
+        let state = self.state.get();
+        if state == State::Queued || (state == State::Scheduled && !self.urgent.get()) {
+            self.update_stats(|s| s.coalesced += 1);
+            return DrawRequest::Pending
+        }
+
+        let delay = if self.urgent.get() { Duration::ZERO } else { self.get_delay() };
+        // any timer that is still running is now stale
+        self.generation.set(self.generation.get() + 1);
+        if delay.is_zero() {
+            self.state.set(State::Queued);
+            DrawRequest::Now
+        } else {
+            self.state.set(State::Scheduled);
+            DrawRequest::After(delay, self.generation.get())
+        }
+    }
+
+    pub fn timer_fired(&self, generation: usize) -> bool {
+        if self.state.get() == State::Scheduled && self.generation.get() == generation {
+            self.state.set(State::Queued);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn begin_frame(&self) -> Instant {
+        let now = Instant::now();
+        self.state.set(State::Idle);
+        self.generation.set(self.generation.get() + 1);
+        self.urgent.set(false);
+        self.last_frame.set(Some(now));
+        now
+    }
+
+    pub fn end_frame(&self, start: Instant) {
+        let elapsed = start.elapsed();
+        self.update_stats(|s| {
+            s.frames += 1;
+            s.last_frame_time = elapsed;
+            s.max_frame_time = s.max_frame_time.max(elapsed);
+            s.total_frame_time += elapsed;
+        });
+    }
+}
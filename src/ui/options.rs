@@ -0,0 +1,111 @@
+use std::collections::{BTreeMap, HashSet};
+use bstr::BString;
+use mlua::prelude::*;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, strum::EnumString, strum::Display)]
+#[strum(ascii_case_insensitive, serialize_all = "lowercase")]
+pub enum Kind {
+    #[default]
+    Any,
+    String,
+    Integer,
+    Number,
+    Boolean,
+    Table,
+    Function,
+}
+
+impl Kind {
+    pub fn matches(self, value: &LuaValue) -> bool {
+        match self {
+            Self::Any => true,
+            Self::String => value.is_string(),
+            Self::Integer => match value {
+                LuaValue::Integer(_) => true,
+                LuaValue::Number(n) => n.fract() == 0.,
+                _ => false,
+            },
+            Self::Number => value.is_number() || value.is_integer(),
+            Self::Boolean => value.is_boolean(),
+            Self::Table => value.is_table(),
+            Self::Function => value.is_function(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct OptionDef {
+    pub name: String,
+    pub kind: Kind,
+    pub default: LuaValue,
+    pub value: LuaValue,
+    pub doc: Option<String>,
+    // called with a new value and returns whether it is allowed (and an error message if not)
+    pub validate: Option<LuaFunction>,
+    // zsh variable that reads and writes this option
+    pub var: Option<BString>,
+    // whether the value was set explicitly rather than being the default
+    pub is_set: bool,
+}
+
+impl std::fmt::Display for OptionDef {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(fmt, "{}: {} = {}", self.name, self.kind, format_value(&self.value))?;
+        if let Some(var) = &self.var {
+            write!(fmt, " (${var})")?;
+        }
+        if let Some(doc) = &self.doc {
+            for line in doc.lines() {
+                write!(fmt, "\n    {line}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// e.g. for displaying in zsh
+pub fn format_value(value: &LuaValue) -> String {
+    match value {
+        LuaValue::String(s) => format!("{:?}", s.to_string_lossy()),
+        value => value.to_string().unwrap_or_else(|_| value.type_name().to_owned()),
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Options {
+    inner: BTreeMap<String, OptionDef>,
+    // zsh variables cannot be created twice so remember which ones exist
+    // these outlive the options themselves, e.g. across a reload
+    vars: HashSet<BString>,
+}
+
+impl Options {
+
+    pub fn get(&self, name: &str) -> Option<&OptionDef> {
+        self.inner.get(name)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut OptionDef> {
+        self.inner.get_mut(name)
+    }
+
+    pub fn define(&mut self, option: OptionDef) {
+        self.inner.insert(option.name.clone(), option);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=&OptionDef> {
+        self.inner.values()
+    }
+
+    pub fn clear(&mut self) {
+        self.inner.clear();
+    }
+
+    pub fn has_var(&self, var: &BString) -> bool {
+        self.vars.contains(var)
+    }
+
+    pub fn add_var(&mut self, var: BString) {
+        self.vars.insert(var);
+    }
+}